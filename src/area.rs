@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+
+// Words per page, pages are only allocated once a nonzero word is written to them
+const PAGE: u64 = 1024;
+
+/**
+A memory area whose words read as zero until written, so its length can grow without allocating
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Area {
+    len: u64,
+    pages: BTreeMap<u64, Vec<u64>>,
+    readonly: bool,
+}
+
+impl Area {
+    pub fn new() -> Area {
+        Area::default()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /**
+    Whether the area was lent to this process without permission to modify it. This is not
+    serialized, it is derived from the shares of the child every time the area is lent.
+    */
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    pub fn get(&self, index: u64) -> u64 {
+        match self.pages.get(&(index / PAGE)) {
+            Some(page) => page[(index % PAGE) as usize],
+            None => 0,
+        }
+    }
+
+    pub fn set(&mut self, index: u64, value: u64) {
+        if value == 0 && !self.pages.contains_key(&(index / PAGE)) {
+            return;
+        }
+        let page = self
+            .pages
+            .entry(index / PAGE)
+            .or_insert_with(|| vec![0; PAGE as usize]);
+        page[(index % PAGE) as usize] = value;
+    }
+
+    /**
+    Change the length, dropping pages past the new end without touching the rest
+    */
+    pub fn resize(&mut self, len: u64) {
+        if len < self.len {
+            let keep = len.div_ceil(PAGE);
+            self.pages.split_off(&keep);
+            if !len.is_multiple_of(PAGE) {
+                if let Some(page) = self.pages.get_mut(&(len / PAGE)) {
+                    for word in page[(len % PAGE) as usize..].iter_mut() {
+                        *word = 0;
+                    }
+                }
+            }
+        }
+        self.len = len;
+    }
+
+    pub fn read(&self, offset: u64, len: u64) -> Vec<u64> {
+        (offset..offset + len).map(|i| self.get(i)).collect()
+    }
+
+    pub fn write(&mut self, offset: u64, words: &[u64]) {
+        for (i, word) in words.iter().enumerate() {
+            self.set(offset + i as u64, *word);
+        }
+    }
+
+    pub fn fill(&mut self, offset: u64, len: u64, value: u64) {
+        for i in offset..offset + len {
+            self.set(i, value);
+        }
+    }
+
+    /**
+    Offsets and words of the allocated pages, cut off at the length
+    */
+    pub fn runs(&self) -> Vec<(u64, &[u64])> {
+        self.pages
+            .iter()
+            .map(|(index, page)| {
+                let start = index * PAGE;
+                (start, &page[..self.len.saturating_sub(start).min(PAGE) as usize])
+            })
+            .collect()
+    }
+
+    /**
+    Number of words in allocated pages up to the length, what the area actually stores
+    */
+    pub fn allocated(&self) -> u64 {
+        self.runs().iter().map(|run| run.1.len() as u64).sum()
+    }
+
+    /**
+    Whether any word from start to end lies in an allocated page
+    */
+    pub fn touched(&self, start: u64, end: u64) -> bool {
+        end > start && self.pages.range(start / PAGE..end.div_ceil(PAGE)).next().is_some()
+    }
+
+    /**
+    Copy of len words starting at offset, copying only the allocated pages
+    */
+    pub fn slice(&self, offset: u64, len: u64) -> Area {
+        let mut area = Area::new();
+        area.resize(len);
+        let first = offset / PAGE;
+        let last = (offset + len).div_ceil(PAGE);
+        for (index, page) in self.pages.range(first..last) {
+            let start = (index * PAGE).max(offset);
+            let end = ((index + 1) * PAGE).min(offset + len).min(self.len);
+            if start < end {
+                let from = (start - index * PAGE) as usize;
+                area.write(start - offset, &page[from..from + (end - start) as usize]);
+            }
+        }
+        area
+    }
+
+    /**
+    Materialize all words, including the ones never written
+    */
+    pub fn to_vec(&self) -> Vec<u64> {
+        let mut words: Vec<u64> = vec![0; self.len as usize];
+        for (index, page) in self.pages.iter() {
+            let start = (index * PAGE) as usize;
+            let end = (start + PAGE as usize).min(words.len());
+            words[start..end].copy_from_slice(&page[..end - start]);
+        }
+        words
+    }
+}
+
+impl From<Vec<u64>> for Area {
+    fn from(words: Vec<u64>) -> Area {
+        let mut area = Area::new();
+        area.len = words.len() as u64;
+        for (index, chunk) in words.chunks(PAGE as usize).enumerate() {
+            if chunk.iter().any(|word| *word != 0) {
+                let mut page = chunk.to_vec();
+                page.resize(PAGE as usize, 0);
+                area.pages.insert(index as u64, page);
+            }
+        }
+        area
+    }
+}
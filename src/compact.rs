@@ -0,0 +1,106 @@
+use ops::*;
+use ops::num_traits::FromPrimitive;
+
+// Marks a word that is stored as is rather than as an instruction
+const ESCAPE: u8 = 0xff;
+
+fn leb128(mut value: u64, bytes: &mut Vec<u8>) {
+    loop {
+        let low = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(low);
+            return;
+        }
+        bytes.push(low | 0x80);
+    }
+}
+
+fn read_leb128(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        if shift == 63 && byte > 1 {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift > 63 {
+            return None;
+        }
+    }
+}
+
+/**
+Encode a code section as one byte per opcode followed by its immediates in LEB128,
+words that are not complete instructions are escaped. The result is the number of bytes
+followed by the bytes packed big-endian into words.
+*/
+pub fn pack(code: &[u64]) -> Vec<u64> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut ip: usize = 0;
+    while ip < code.len() {
+        let length = match IS::from_u64(code[ip]) {
+            Some(i) if code[ip] < ESCAPE as u64 => requirement(i).length as usize,
+            _ => 0,
+        };
+        if length == 0 || ip + length > code.len() {
+            bytes.push(ESCAPE);
+            leb128(code[ip], &mut bytes);
+            ip += 1;
+            continue;
+        }
+        bytes.push(code[ip] as u8);
+        for word in code[ip + 1..ip + length].iter() {
+            leb128(*word, &mut bytes);
+        }
+        ip += length;
+    }
+
+    let mut words: Vec<u64> = vec![bytes.len() as u64];
+    for chunk in bytes.chunks(8) {
+        let mut word: u64 = 0;
+        for i in 0..8 {
+            word = (word << 8) | *chunk.get(i).unwrap_or(&0) as u64;
+        }
+        words.push(word);
+    }
+    words
+}
+
+/**
+Decode a section written by pack back to one word per opcode and immediate
+*/
+pub fn unpack(words: &[u64]) -> Option<Vec<u64>> {
+    let count = *words.first()? as usize;
+    if count > (words.len() - 1).saturating_mul(8) {
+        return None;
+    }
+    let bytes: Vec<u8> = words[1..]
+        .iter()
+        .flat_map(|word| (0..8).rev().map(move |i| (word >> (i * 8)) as u8))
+        .take(count)
+        .collect();
+
+    let mut code: Vec<u64> = Vec::new();
+    let mut pos: usize = 0;
+    while pos < bytes.len() {
+        let opcode = bytes[pos];
+        pos += 1;
+        if opcode == ESCAPE {
+            code.push(read_leb128(&bytes, &mut pos)?);
+            continue;
+        }
+        let i = IS::from_u8(opcode)?;
+        code.push(opcode as u64);
+        for _immediate in 1..requirement(i).length {
+            code.push(read_leb128(&bytes, &mut pos)?);
+        }
+    }
+    Some(code)
+}
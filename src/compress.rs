@@ -0,0 +1,218 @@
+#[cfg(feature = "deflate")]
+use byteorder::{BigEndian, ByteOrder};
+
+// First word of a compressed snapshot, never a valid status
+pub const MAGIC: u64 = 0x52564d5a_00000000;
+
+// Codecs, stored after the magic word followed by the uncompressed length
+pub const ZEROS: u64 = 1;
+pub const DEFLATE: u64 = 2;
+
+// Shorter runs of zeros are cheaper to keep among the literals
+const MIN_RUN: usize = 3;
+
+// Largest uncompressed length accepted in words, the stored length is checked against it before
+// anything is allocated since zero runs can expand without bound
+pub const MAX_LEN: u64 = 1 << 28;
+
+// Most bytes a single byte of a zlib stream can expand to
+#[cfg(feature = "deflate")]
+const MAX_RATIO: usize = 1032;
+
+fn run(flat: &[u64], i: usize) -> usize {
+    flat[i..]
+        .iter()
+        .take(MIN_RUN)
+        .take_while(|word| **word == 0)
+        .count()
+}
+
+/**
+Alternate between a count of literal words with the words themselves and a count of zeros
+*/
+fn zeros(flat: &[u64]) -> Vec<u64> {
+    let mut out: Vec<u64> = Vec::new();
+    let mut i = 0;
+    while i < flat.len() {
+        let start = i;
+        while i < flat.len() && (flat[i] != 0 || run(flat, i) < MIN_RUN.min(flat.len() - i)) {
+            i += 1;
+        }
+        out.push((i - start) as u64);
+        out.extend(&flat[start..i]);
+
+        let start = i;
+        while i < flat.len() && flat[i] == 0 {
+            i += 1;
+        }
+        out.push((i - start) as u64);
+    }
+    out
+}
+
+fn unzeros(packed: &[u64], len: usize) -> Result<Vec<u64>, String> {
+    let mut flat: Vec<u64> = Vec::new();
+    let mut i = 0;
+    while i + 1 < packed.len() {
+        let literals = packed[i] as usize;
+        if literals > packed.len() - i - 2 {
+            return Err("truncated zero run encoding".to_string());
+        }
+        flat.extend(&packed[i + 1..i + 1 + literals]);
+        i += 1 + literals;
+        let count = packed[i] as usize;
+        if count > len - flat.len().min(len) {
+            return Err("zero run past the stored length".to_string());
+        }
+        flat.resize(flat.len() + count, 0);
+        i += 1;
+    }
+    if i != packed.len() || flat.len() != len {
+        return Err("zero run encoding does not match the stored length".to_string());
+    }
+    Ok(flat)
+}
+
+#[cfg(feature = "deflate")]
+fn deflate(flat: &[u64]) -> Vec<u64> {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut vec8: Vec<u8> = vec![0; flat.len() * 8];
+    BigEndian::write_u64_into(flat, &mut vec8);
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&vec8).unwrap();
+    let mut bytes = encoder.finish().unwrap();
+
+    let count = bytes.len();
+    bytes.resize(count.div_ceil(8) * 8, 0);
+    let mut out: Vec<u64> = vec![0; 1 + bytes.len() / 8];
+    out[0] = count as u64;
+    BigEndian::read_u64_into(&bytes, &mut out[1..]);
+    out
+}
+
+#[cfg(feature = "deflate")]
+fn inflate(packed: &[u64], len: usize) -> Result<Vec<u64>, String> {
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    let count = *packed.first().ok_or("missing deflate length")? as usize;
+    let mut bytes: Vec<u8> = vec![0; (packed.len() - 1) * 8];
+    BigEndian::write_u64_into(&packed[1..], &mut bytes);
+    if count > bytes.len() {
+        return Err("truncated deflate stream".to_string());
+    }
+    if len.saturating_mul(8) > count.saturating_mul(MAX_RATIO) {
+        return Err("stored length is more than the deflate stream can hold".to_string());
+    }
+
+    // One byte more than expected is enough to notice a stream that is too long
+    let mut decoded: Vec<u8> = Vec::new();
+    ZlibDecoder::new(&bytes[..count])
+        .take(len as u64 * 8 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| e.to_string())?;
+    if decoded.len() != len * 8 {
+        return Err("deflate stream does not match the stored length".to_string());
+    }
+    let mut flat: Vec<u64> = vec![0; len];
+    BigEndian::read_u64_into(&decoded, &mut flat);
+    Ok(flat)
+}
+
+#[cfg(not(feature = "deflate"))]
+fn inflate(_packed: &[u64], _len: usize) -> Result<Vec<u64>, String> {
+    Err("built without the deflate feature".to_string())
+}
+
+/**
+Codec by name, zeros or deflate
+*/
+pub fn codec(name: &str) -> Option<u64> {
+    match name {
+        "zeros" => Some(ZEROS),
+        "deflate" => Some(DEFLATE),
+        _ => None,
+    }
+}
+
+/**
+Compress serialized words with one of the codecs, DEFLATE needs the deflate feature
+*/
+pub fn compress(flat: &[u64], codec: u64) -> Result<Vec<u64>, String> {
+    let packed = match codec {
+        ZEROS => zeros(flat),
+        #[cfg(feature = "deflate")]
+        DEFLATE => deflate(flat),
+        #[cfg(not(feature = "deflate"))]
+        DEFLATE => return Err("built without the deflate feature".to_string()),
+        _ => return Err(format!("codec {} is not available", codec)),
+    };
+    let mut out: Vec<u64> = vec![MAGIC, codec, flat.len() as u64];
+    out.extend(packed);
+    Ok(out)
+}
+
+/**
+Undo compress, words without the magic word are returned as they are
+*/
+pub fn decompress(flat: Vec<u64>) -> Result<Vec<u64>, String> {
+    if flat.first() != Some(&MAGIC) {
+        return Ok(flat);
+    }
+    if flat.len() < 3 {
+        return Err("truncated compression header".to_string());
+    }
+    if flat[2] > MAX_LEN {
+        return Err(format!("stored length {} is above the limit of {} words", flat[2], MAX_LEN));
+    }
+    let len = flat[2] as usize;
+    match flat[1] {
+        ZEROS => unzeros(&flat[3..], len),
+        DEFLATE => inflate(&flat[3..], len),
+        codec => Err(format!("unknown codec {}", codec)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<u64> {
+        let mut flat = vec![0; 5000];
+        flat[1] = 7;
+        flat[2] = 8;
+        flat[4000] = u64::MAX;
+        flat
+    }
+
+    #[test]
+    fn zero_runs_round_trip() {
+        let packed = compress(&sample(), ZEROS).unwrap();
+        assert!(packed.len() < 20);
+        assert_eq!(decompress(packed).unwrap(), sample());
+        assert_eq!(decompress(vec![1, 2, 3]).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn deflate_round_trips() {
+        let packed = compress(&sample(), DEFLATE).unwrap();
+        assert!(packed.len() < 100);
+        assert_eq!(decompress(packed).unwrap(), sample());
+    }
+
+    #[test]
+    fn stored_lengths_are_checked_before_allocating() {
+        // A single zero run claiming far more words than allowed
+        assert!(decompress(vec![MAGIC, ZEROS, u64::MAX, 0, u64::MAX]).is_err());
+        assert!(decompress(vec![MAGIC, ZEROS, MAX_LEN + 1, 0, MAX_LEN + 1]).is_err());
+        assert!(decompress(vec![MAGIC, DEFLATE, 1 << 40, 1, 0]).is_err());
+
+        let mut packed = compress(&sample(), ZEROS).unwrap();
+        packed[2] += 1;
+        assert!(decompress(packed).is_err());
+    }
+}
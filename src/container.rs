@@ -1,12 +1,27 @@
 use vm::*;
 use formats::*;
+use observer::*;
+use record::*;
 
 use std::collections::HashMap;
 use std::time::Instant;
 
-pub struct Container {
+/**
+How host calls are served: by the host functions, by the host functions while logging their
+effects, or from such a log without involving the host
+*/
+pub enum Host {
+    Live,
+    Record(Recording),
+    Replay(Recording, usize),
+}
+
+pub struct Container<O: Observer = Silent> {
     sharp: Process,
     table: HashMap<u64, fn(&mut Process)>,
+    depth: u64,
+    observer: O,
+    host: Host,
 }
 
 #[allow(dead_code)]
@@ -16,9 +31,60 @@ impl Container {
         Container {
             sharp: sharp.clone(),
             table: tab,
+            depth: MAX_DEPTH,
+            observer: Silent,
+            host: Host::Live,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<O: Observer> Container<O> {
+    /**
+    Install an observer that is told about everything the contained process does
+    */
+    pub fn with_observer<P: Observer>(self, observer: P) -> Container<P> {
+        Container {
+            sharp: self.sharp,
+            table: self.table,
+            depth: self.depth,
+            observer,
+            host: self.host,
+        }
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /**
+    Log every host call from now on
+    */
+    pub fn record(&mut self) {
+        self.host = Host::Record(Recording::new());
+    }
+
+    /**
+    Serve host calls from a recording instead of the host functions
+    */
+    pub fn replay(&mut self, recording: Recording) {
+        self.host = Host::Replay(recording, 0);
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        match self.host {
+            Host::Record(ref recording) => Some(recording),
+            _ => None,
         }
     }
 
+    /**
+    Limit how many processes may be nested below the contained one
+    */
+    pub fn set_depth(&mut self, depth: u64) {
+        self.depth = depth;
+    }
+
     pub fn get(&self, key: u64) -> fn(&mut Process) {
         self.table[&key]
     }
@@ -37,7 +103,14 @@ impl Container {
         loop {
             let now = Instant::now();
             // TODO why clone()?
-            self.sharp = run(self.sharp.clone(), START_GAS, 10000000000000000, debug);
+            self.sharp = observe(
+                self.sharp.clone(),
+                START_GAS,
+                10000000000000000,
+                self.depth,
+                debug,
+                &mut self.observer,
+            );
             let elapsed = now.elapsed();
 
             if false {
@@ -56,14 +129,48 @@ impl Container {
             {
 
                 let funid = self.sharp.stack[stacklen - 2];
-                let func = self.table.get(&funid).map(|x| *x);
 
-                match func {
-                    Some(func) => func(&mut self.sharp),
-                    None => {
-                        println!("invalid op");
+                if let Host::Replay(ref recording, ref mut next) = self.host {
+                    // Stop as soon as the guest asks for something else than it did before
+                    match recording.calls.get(*next) {
+                        Some(call) if call.funid == funid && call.stack == self.sharp.stack => {
+                            match try_d(&call.effect) {
+                                Some(sharp) => self.sharp = sharp,
+                                None => {
+                                    println!("host call {} left no valid process", next);
+                                    break;
+                                }
+                            }
+                            *next += 1;
+                        }
+                        _ => {
+                            println!("replay diverged at host call {}", next);
+                            break;
+                        }
                     }
-                };
+                } else {
+                    let stack = match self.host {
+                        Host::Record(_) => self.sharp.stack.clone(),
+                        _ => Vec::new(),
+                    };
+
+                    let func = self.table.get(&funid).copied();
+
+                    match func {
+                        Some(func) => func(&mut self.sharp),
+                        None => {
+                            println!("invalid op");
+                        }
+                    };
+
+                    if let Host::Record(ref mut recording) = self.host {
+                        recording.calls.push(HostCall {
+                            funid,
+                            stack,
+                            effect: s(&self.sharp),
+                        });
+                    }
+                }
 
                 self.sharp.stack.pop();
             } else if self.sharp.header.status != Stati::NOR as u64 {
@@ -83,3 +190,50 @@ impl Container {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Doubles the argument on top of the stack into the slot of the function id
+    fn double(sharp: &mut Process) {
+        let len = sharp.stack.len();
+        sharp.stack[len - 2] = sharp.stack[len - 1] * 2;
+    }
+
+    // PUSH 7, PUSH 5, YIELD, HALT: ask host function 7 to double 5
+    fn asking() -> Process {
+        d(&[0, 0, 0, 0, 0, 6, 0, 0, 0, 6, 7, 6, 5, 2, 0])
+    }
+
+    #[test]
+    fn recordings_replay_without_the_host() {
+        let mut recorder = Container::new(asking());
+        recorder.add_func(7, double);
+        recorder.record();
+        recorder.run_io();
+        assert_eq!(recorder.sharp.stack, vec![10]);
+        assert_eq!(recorder.sharp.header.status, Stati::HLT as u64);
+
+        let flat = recorder.recording().unwrap().to_words();
+        let mut replayer = Container::new(asking());
+        replayer.replay(Recording::from_words(&flat).unwrap());
+        replayer.run_io();
+        assert_eq!(s(&replayer.sharp), s(&recorder.sharp));
+    }
+
+    #[test]
+    fn replays_stop_at_effects_that_are_not_processes() {
+        let mut replayer = Container::new(asking());
+        replayer.replay(Recording {
+            calls: vec![HostCall {
+                funid: 7,
+                stack: vec![7, 5],
+                effect: vec![1, 2, 3],
+            }],
+        });
+        replayer.run_io();
+        assert_eq!(replayer.sharp.header.status, Stati::YLD as u64);
+        assert_eq!(replayer.sharp.stack, vec![7, 5]);
+    }
+}
@@ -0,0 +1,189 @@
+use ops::*;
+use formats::*;
+use observer::*;
+use disasm::*;
+use store::*;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/**
+A distinct code section and how often each of its addresses was executed
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub code: Vec<u64>,
+    pub opset: u64,
+    pub hits: Vec<u64>,
+}
+
+/**
+Observer collecting hit counts for the code of every process it sees, including children
+started with RUN. Processes running the same code share one entry, found by the code hash.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    pub programs: Vec<Program>,
+    index: HashMap<(Hash, u64), usize>,
+    current: Vec<usize>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    fn program(&mut self, code: &[u64], opset: u64) -> usize {
+        let programs = &mut self.programs;
+        *self.index.entry((hash(code), opset)).or_insert_with(|| {
+            programs.push(Program {
+                code: code.to_vec(),
+                opset,
+                hits: vec![0; code.len()],
+            });
+            programs.len() - 1
+        })
+    }
+
+    /**
+    Add the hit counts of another collection, for example from a different test run
+    */
+    pub fn merge(&mut self, other: &Coverage) {
+        for program in other.programs.iter() {
+            let index = self.program(&program.code, program.opset);
+            for (hits, more) in self.programs[index].hits.iter_mut().zip(program.hits.iter()) {
+                *hits += *more;
+            }
+        }
+    }
+
+    /**
+    Flatten to words: the number of programs, then per program its opcode set,
+    code length, code and hit counts
+    */
+    pub fn to_words(&self) -> Vec<u64> {
+        let mut flat: Vec<u64> = vec![self.programs.len() as u64];
+        for program in self.programs.iter() {
+            flat.push(program.opset);
+            flat.push(program.code.len() as u64);
+            flat.extend(&program.code);
+            flat.extend(&program.hits);
+        }
+        flat
+    }
+
+    /**
+    Read what to_words wrote, None if the words end early or lengths do not add up
+    */
+    pub fn from_words(flat: &[u64]) -> Option<Coverage> {
+        let mut coverage = Coverage::new();
+        let mut start: usize = 1;
+        for _program in 0..*flat.first()? {
+            let opset = *flat.get(start)?;
+            let codelen = *flat.get(start + 1)? as usize;
+            start += 2;
+            let middle = start.checked_add(codelen)?;
+            let end = middle.checked_add(codelen)?;
+            let code = flat.get(start..middle)?.to_vec();
+            let index = coverage.program(&code, opset);
+            let more = flat.get(middle..end)?;
+            for (hits, more) in coverage.programs[index].hits.iter_mut().zip(more) {
+                *hits += *more;
+            }
+            start = end;
+        }
+        if start != flat.len() {
+            return None;
+        }
+        Some(coverage)
+    }
+
+    /**
+    Disassembly of every program with hit counts in front, unexecuted instructions marked with #####
+    */
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for (index, program) in self.programs.iter().enumerate() {
+            let lines = range(&program.code, program.opset, 0, program.code.len() as u64);
+            let covered = lines
+                .iter()
+                .filter(|(ip, _)| program.hits[*ip as usize] > 0)
+                .count();
+            writeln!(
+                out,
+                "program {} (opcode set {}): {} of {} instructions covered",
+                index,
+                program.opset,
+                covered,
+                lines.len()
+            )
+            .unwrap();
+            for (ip, line) in lines.iter() {
+                let hits = program.hits[*ip as usize];
+                let count = if hits > 0 { hits.to_string() } else { "#####".to_string() };
+                writeln!(out, "{:>12} {:>8}  {}", count, ip, line).unwrap();
+            }
+        }
+        out
+    }
+}
+
+impl Observer for Coverage {
+    fn before(&mut self, state: &Process, depth: usize, _instr: IS) {
+        if self.current.len() <= depth {
+            let index = self.program(&state.code, state.opset);
+            self.current.push(index);
+        }
+        let index = self.current[depth];
+        self.programs[index].hits[state.header.ip as usize] += 1;
+    }
+
+    fn status(&mut self, _state: &Process, depth: usize, _status: u64) {
+        if depth == 0 {
+            self.current.clear();
+        }
+    }
+
+    fn enter(&mut self, child: &Process, depth: usize) {
+        self.current.truncate(depth);
+        let index = self.program(&child.code, child.opset);
+        self.current.push(index);
+    }
+
+    fn exit(&mut self, _child: &Process, depth: usize) {
+        self.current.truncate(depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coverage() -> Coverage {
+        let mut coverage = Coverage::new();
+        let index = coverage.program(&[6, 1, 0], 1);
+        coverage.programs[index].hits = vec![2, 0, 2];
+        coverage.program(&[0], 0);
+        coverage
+    }
+
+    #[test]
+    fn words_round_trip_and_merge_by_code() {
+        let flat = coverage().to_words();
+        let mut back = Coverage::from_words(&flat).unwrap();
+        assert_eq!(back.programs, coverage().programs);
+
+        back.merge(&coverage());
+        assert_eq!(back.programs.len(), 2);
+        assert_eq!(back.programs[0].hits, vec![4, 0, 4]);
+    }
+
+    #[test]
+    fn truncated_words_are_rejected() {
+        let flat = coverage().to_words();
+        for len in 0..flat.len() {
+            assert_eq!(Coverage::from_words(&flat[..len]).map(|c| c.programs), None);
+        }
+        assert!(Coverage::from_words(&[1, 0, u64::MAX]).is_none());
+    }
+}
@@ -0,0 +1,160 @@
+use ops::*;
+use formats::*;
+use observer::*;
+use record::*;
+use vm::*;
+
+// Instructions between checkpoints
+pub const INTERVAL: u64 = 1000;
+
+/**
+Counts executed instructions at all depths and pauses once target is reached
+*/
+struct Counter {
+    executed: u64,
+    target: u64,
+}
+
+impl Observer for Counter {
+    fn before(&mut self, _state: &Process, _depth: usize, _instr: IS) {
+        self.executed += 1;
+    }
+
+    fn pause(&mut self) -> bool {
+        self.executed >= self.target
+    }
+}
+
+/**
+Moves a process to any instruction count, backwards included. The whole nesting is
+checkpointed every INTERVAL instructions, and every move replays from the nearest
+checkpoint, so states do not depend on the order in which they were visited.
+Checkpoints keep the running children as they are, so continuing from one charges
+exactly what running straight through would.
+Yields to the host are served from a recording if there is one, otherwise they end the run.
+*/
+pub struct Debugger {
+    // Nest and next host call after every INTERVAL instructions
+    checkpoints: Vec<(Nest, usize)>,
+    recording: Recording,
+    pub position: u64,
+    pub nest: Nest,
+}
+
+impl Debugger {
+    pub fn new(sharp: Process, recording: Recording) -> Debugger {
+        let gas = sharp.header.gas;
+        let mem = sharp.header.mem;
+        let nest = Nest::new(sharp, gas, mem);
+        Debugger {
+            checkpoints: vec![(nest.clone(), 0)],
+            recording,
+            position: 0,
+            nest,
+        }
+    }
+
+    /**
+    Run from a checkpoint taken after start instructions until target instructions
+    have executed or the root stops
+    */
+    fn advance(&self, checkpoint: &(Nest, usize), start: u64, target: u64) -> (Nest, usize, u64) {
+        let mut nest = checkpoint.0.clone();
+        let mut next = checkpoint.1;
+        let mut counter = Counter {
+            executed: start,
+            target,
+        };
+        while counter.executed < target && resume(&mut nest, MAX_DEPTH, false, &mut counter) {
+            let root = &nest.states[0];
+            if root.header.status != Stati::YLD as u64 || root.stack.len() < 2 {
+                break;
+            }
+            let funid = root.stack[root.stack.len() - 2];
+            match self.recording.calls.get(next) {
+                Some(call) if call.funid == funid && call.stack == root.stack => {
+                    let mut sharp = match try_d(&call.effect) {
+                        Some(sharp) => sharp,
+                        None => break,
+                    };
+                    sharp.stack.pop();
+                    let (gas, mem) = (sharp.header.gas, sharp.header.mem);
+                    nest = Nest::new(sharp, gas, mem);
+                    next += 1;
+                }
+                _ => break,
+            }
+        }
+        (nest, next, counter.executed)
+    }
+
+    /**
+    Go to the state after target instructions, or the last one before the process stopped
+    */
+    pub fn seek(&mut self, target: u64) {
+        // Fill in missing checkpoints up to the target
+        while ((self.checkpoints.len() as u64) * INTERVAL) <= target {
+            let start = (self.checkpoints.len() as u64 - 1) * INTERVAL;
+            let last = &self.checkpoints[self.checkpoints.len() - 1];
+            let (nest, next, executed) = self.advance(last, start, start + INTERVAL);
+            if executed < start + INTERVAL {
+                break;
+            }
+            self.checkpoints.push((nest, next));
+        }
+
+        let index = ((target / INTERVAL) as usize).min(self.checkpoints.len() - 1);
+        let (nest, _, executed) =
+            self.advance(&self.checkpoints[index], index as u64 * INTERVAL, target);
+        self.nest = nest;
+        self.position = executed;
+    }
+
+    /**
+    The innermost process that was running, with its depth
+    */
+    pub fn active(&self) -> (usize, &Process) {
+        let depth = self.nest.states.len() - 1;
+        (depth, &self.nest.states[depth])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(code: &[u64], areas: &[Vec<u64>]) -> Process {
+        let mut flat = vec![0, 0, 0, 0, 0, code.len() as u64, 0, 0, areas.len() as u64];
+        flat.extend(code);
+        for area in areas {
+            flat.push(area.len() as u64);
+            flat.extend(area);
+        }
+        let mut sharp = d(&flat);
+        sharp.opset = OPSET;
+        sharp
+    }
+
+    #[test]
+    fn pausing_inside_a_child_charges_the_same_as_running_through() {
+        // Count down from 1500: PUSH 1500, PUSH 1, SUB, DUP, PUSH 12, JZ, PUSH 2, JUMP, HALT
+        let count = process(&[6, 1500, 6, 1, 24, 8, 6, 12, 5, 6, 2, 4, 0], &[]);
+        // PUSH 0, PUSH gas, PUSH mem, RUN, HALT
+        let code = [6, 0, 6, 100_000, 6, 10_000_000, 3, 0];
+        let mut sharp = process(&code, &[s(&count), vec![0; 500]]);
+        sharp.header.gas = 1_000_000;
+        sharp.header.mem = 100_000_000;
+
+        let straight = run(sharp.clone(), 1_000_000, 100_000_000, MAX_DEPTH, false);
+
+        let mut debugger = Debugger::new(sharp, Recording::new());
+        for target in [1, 999, 1000, 1001, 2500, 4000, 1_000_000].iter() {
+            debugger.seek(*target);
+        }
+        assert!(debugger.checkpoints.len() > 5);
+        assert_eq!(debugger.nest.states.len(), 1);
+        let (_, root) = debugger.active();
+        assert_eq!(root.header.status, Stati::HLT as u64);
+        assert_eq!(s(root), s(&straight));
+    }
+}
@@ -0,0 +1,231 @@
+use area::*;
+use formats::*;
+
+use std::collections::BTreeMap;
+
+/**
+Ranges of words that differ between two sequences, comparing only the spans given, which must
+cover every word that may differ below the shorter length. A length change counts as a changed
+tail.
+*/
+fn ranges<W: Words + ?Sized>(a: &W, b: &W, spans: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut changed: Vec<(u64, u64)> = Vec::new();
+    let mut push = |first: u64, end: u64| match changed.last_mut() {
+        Some(last) if last.1 == first => last.1 = end,
+        _ => changed.push((first, end)),
+    };
+    for &(from, to) in spans {
+        let mut start: Option<u64> = None;
+        for i in from..to {
+            if a.word(i) != b.word(i) {
+                if start.is_none() {
+                    start = Some(i);
+                }
+            } else if let Some(first) = start.take() {
+                push(first, i);
+            }
+        }
+        if let Some(first) = start {
+            push(first, to);
+        }
+    }
+    let (shorter, longer) = (a.size().min(b.size()), a.size().max(b.size()));
+    if shorter < longer {
+        push(shorter, longer);
+    }
+    changed
+}
+
+/**
+Spans of the pages allocated on either side, below the shorter length, outside of which both
+areas hold zeros
+*/
+fn pages(a: &Area, b: &Area) -> Vec<(u64, u64)> {
+    let shorter = a.len().min(b.len());
+    let mut spans: Vec<(u64, u64)> = a
+        .runs()
+        .iter()
+        .chain(b.runs().iter())
+        .map(|run| (run.0, (run.0 + run.1.len() as u64).min(shorter)))
+        .filter(|span| span.0 < span.1)
+        .collect();
+    spans.sort();
+    spans.dedup();
+    spans
+}
+
+fn changes<W: Words + ?Sized>(
+    name: &str,
+    a: &W,
+    b: &W,
+    spans: &[(u64, u64)],
+    out: &mut Vec<String>,
+) {
+    if a.size() != b.size() {
+        out.push(format!("{}: length {} -> {}", name, a.size(), b.size()));
+    }
+    for (start, end) in ranges(a, b, spans) {
+        if end - start <= 8 {
+            let before: Vec<String> =
+                (start..end.min(a.size())).map(|i| a.word(i).to_string()).collect();
+            let after: Vec<String> =
+                (start..end.min(b.size())).map(|i| b.word(i).to_string()).collect();
+            out.push(format!(
+                "{} {}..{}: [{}] -> [{}]",
+                name,
+                start,
+                end,
+                before.join(", "),
+                after.join(", ")
+            ));
+        } else {
+            out.push(format!("{} {}..{}: {} words changed", name, start, end, end - start));
+        }
+    }
+}
+
+fn words(name: &str, a: &[u64], b: &[u64], out: &mut Vec<String>) {
+    changes(name, a, b, &[(0, a.len().min(b.len()) as u64)], out);
+}
+
+fn field(name: &str, a: u64, b: u64, out: &mut Vec<String>) {
+    if a != b {
+        out.push(format!("{}: {} -> {}", name, a, b));
+    }
+}
+
+fn keys(map: &[u64]) -> BTreeMap<u64, u64> {
+    map.chunks(2)
+        .map(|pair| (pair[0], pair.get(1).cloned().unwrap_or(0)))
+        .collect()
+}
+
+fn process(prefix: &str, a: &Process, b: &Process, out: &mut Vec<String>) {
+    field(&format!("{}status", prefix), a.header.status, b.header.status, out);
+    field(&format!("{}rec", prefix), a.header.rec, b.header.rec, out);
+    field(&format!("{}gas", prefix), a.header.gas, b.header.gas, out);
+    field(&format!("{}mem", prefix), a.header.mem, b.header.mem, out);
+    field(&format!("{}ip", prefix), a.header.ip, b.header.ip, out);
+    field(&format!("{}opset", prefix), a.opset, b.opset, out);
+    if a.fault != b.fault {
+        out.push(format!("{}fault: {:?} -> {:?}", prefix, a.fault, b.fault));
+    }
+
+    words(&format!("{}code", prefix), &a.code, &b.code, out);
+    words(&format!("{}stack", prefix), &a.stack, &b.stack, out);
+    words(&format!("{}calls", prefix), &a.calls, &b.calls, out);
+    words(&format!("{}outbox", prefix), &a.outbox, &b.outbox, out);
+    words(&format!("{}inbox", prefix), &a.inbox, &b.inbox, out);
+
+    let (amap, bmap) = (keys(&a.map), keys(&b.map));
+    for (key, value) in amap.iter() {
+        match bmap.get(key) {
+            None => out.push(format!("{}map {}: removed, was {}", prefix, key, value)),
+            Some(other) if other != value => {
+                out.push(format!("{}map {}: {} -> {}", prefix, key, value, other))
+            }
+            _ => {}
+        }
+    }
+    for (key, value) in bmap.iter() {
+        if !amap.contains_key(key) {
+            out.push(format!("{}map {}: added {}", prefix, key, value));
+        }
+    }
+
+    if a.shares != b.shares {
+        out.push(format!("{}shares: {:?} -> {:?}", prefix, a.shares, b.shares));
+    }
+    field(
+        &format!("{}areas", prefix),
+        a.memory.len() as u64,
+        b.memory.len() as u64,
+        out,
+    );
+    for (index, (aarea, barea)) in a.memory.iter().zip(b.memory.iter()).enumerate() {
+        let spans = pages(aarea, barea);
+        if aarea.len() == barea.len() && ranges(aarea, barea, &spans).is_empty() {
+            continue;
+        }
+        let name = format!("{}area {}", prefix, index);
+        match (try_d_area(aarea), try_d_area(barea)) {
+            (Some(achild), Some(bchild)) => process(&format!("{} ", name), &achild, &bchild, out),
+            _ => changes(&name, aarea, barea, &spans, out),
+        }
+    }
+    for (index, area) in a.memory.iter().enumerate().skip(b.memory.len()) {
+        out.push(format!("{}area {}: removed, was {} words", prefix, index, area.len()));
+    }
+    for (index, area) in b.memory.iter().enumerate().skip(a.memory.len()) {
+        out.push(format!("{}area {}: added {} words", prefix, index, area.len()));
+    }
+}
+
+/**
+Describe every difference between two snapshots, one line each, descending into areas
+that hold nested processes on both sides
+*/
+pub fn diff(a: &Process, b: &Process) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    process("", a, b, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sharp(stack: &[u64], areas: &[Area]) -> Process {
+        let mut sharp = d(&[0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        sharp.stack = stack.to_vec();
+        sharp.memory = areas.to_vec();
+        sharp
+    }
+
+    #[test]
+    fn changed_stacks_and_areas_are_described() {
+        let a = sharp(&[1, 2, 3], &[Area::from(vec![0, 0, 5])]);
+        let b = sharp(&[1, 7, 3, 4], &[Area::from(vec![0, 6, 5])]);
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                "stack: length 3 -> 4",
+                "stack 1..2: [2] -> [7]",
+                "stack 3..4: [] -> [4]",
+                "area 0 1..2: [0] -> [6]",
+            ]
+        );
+        assert!(diff(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_areas_are_described() {
+        let a = sharp(&[], &[Area::from(vec![1])]);
+        let b = sharp(&[], &[Area::from(vec![1]), Area::from(vec![2, 3])]);
+        assert_eq!(diff(&a, &b), vec!["areas: 1 -> 2", "area 1: added 2 words"]);
+        assert_eq!(diff(&b, &a), vec!["areas: 2 -> 1", "area 1: removed, was 2 words"]);
+    }
+
+    #[test]
+    fn huge_sparse_areas_are_compared_by_page() {
+        let mut huge = Area::new();
+        huge.resize(1 << 40);
+        let mut changed = huge.clone();
+        changed.set(1 << 39, 9);
+        let mut longer = huge.clone();
+        longer.resize((1 << 40) + 20);
+        let a = sharp(&[], &[huge]);
+        assert!(diff(&a, &a).is_empty());
+        assert_eq!(
+            diff(&a, &sharp(&[], &[changed])),
+            vec!["area 0 549755813888..549755813889: [0] -> [9]"]
+        );
+        assert_eq!(
+            diff(&a, &sharp(&[], &[longer])),
+            vec![
+                "area 0: length 1099511627776 -> 1099511627796",
+                "area 0 1099511627776..1099511627796: 20 words changed",
+            ]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use ops::*;
+use flow::*;
+
+/**
+Render the instruction at ip as its mnemonic followed by any immediates
+*/
+pub fn instruction(code: &[u64], ip: usize, set: u64) -> String {
+    match lookup(code[ip], set) {
+        None => format!("?{}", code[ip]),
+        Some(i) => {
+            let mut line = format!("{:?}", i);
+            let end = ip + length(code, ip, set);
+            if end > code.len() {
+                line.push_str(" <truncated>");
+            } else {
+                for word in code[ip + 1..end].iter() {
+                    line.push_str(&format!(" {}", word));
+                }
+            }
+            line
+        }
+    }
+}
+
+/**
+Disassemble a range of a code section into address and instruction pairs
+*/
+pub fn range(code: &[u64], set: u64, start: u64, end: u64) -> Vec<(u64, String)> {
+    let mut lines: Vec<(u64, String)> = Vec::new();
+    let mut ip = start as usize;
+    while ip < (end as usize).min(code.len()) {
+        lines.push((ip as u64, instruction(code, ip, set)));
+        ip += length(code, ip, set);
+    }
+    lines
+}
+
+/**
+Disassemble a whole code section
+*/
+#[allow(dead_code)]
+pub fn disassemble(code: &[u64], set: u64) -> Vec<(u64, String)> {
+    range(code, set, 0, code.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(code: &[u64], set: u64) -> Vec<String> {
+        disassemble(code, set)
+            .into_iter()
+            .map(|(ip, line)| format!("{}: {}", ip, line))
+            .collect()
+    }
+
+    #[test]
+    fn instructions_are_listed_with_their_immediates() {
+        // Branch past a call to a subroutine, then an unknown opcode and a cut off PUSH
+        let code = [6, 1, 6, 9, 5, 6, 10, 33, 0, 0, 6, 4, 34, 200, 6];
+        assert_eq!(
+            listing(&code, OPSET),
+            vec![
+                "0: PUSH 1",
+                "2: PUSH 9",
+                "4: JZ",
+                "5: PUSH 10",
+                "7: CALL",
+                "8: HALT",
+                "9: HALT",
+                "10: PUSH 4",
+                "12: RET",
+                "13: ?200",
+                "14: PUSH <truncated>",
+            ]
+        );
+        // CALL and RET are unknown before the set that introduced them
+        assert_eq!(
+            listing(&code[5..13], 0),
+            vec!["0: PUSH 10", "2: ?33", "3: HALT", "4: HALT", "5: PUSH 4", "7: ?34"]
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use flow::*;
+use disasm::*;
+
+/**
+Render the control-flow graph of a code section in Graphviz DOT format
+*/
+pub fn dot(code: &[u64], set: u64) -> String {
+    let graph = build(code, set);
+    let mut out = String::new();
+    out.push_str("digraph code {\n");
+    out.push_str("    node [shape=box, fontname=monospace];\n");
+
+    for block in graph.blocks.iter() {
+        let mut label = String::new();
+        for (ip, line) in range(code, set, block.start, block.end) {
+            label.push_str(&format!("{}: {}\\l", ip, line));
+        }
+        let mut style: Vec<&str> = Vec::new();
+        if !block.reachable {
+            style.push("style=dashed, fontcolor=gray, color=gray");
+        }
+        if block.dynamic {
+            style.push("peripheries=2, color=red");
+        }
+        let extra = if style.is_empty() {
+            String::new()
+        } else {
+            format!(", {}", style.join(", "))
+        };
+        out.push_str(&format!(
+            "    b{} [label=\"{}\"{}];\n",
+            block.start, label, extra
+        ));
+    }
+
+    for block in graph.blocks.iter() {
+        for succ in block.succs.iter() {
+            out.push_str(&format!("    b{} -> b{};\n", block.start, succ));
+        }
+        if block.dynamic {
+            out.push_str(&format!(
+                "    d{} [label=\"?\", shape=circle, color=red];\n",
+                block.start
+            ));
+            out.push_str(&format!(
+                "    b{} -> d{} [style=dashed, color=red];\n",
+                block.start, block.start
+            ));
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ops::*;
+
+    #[test]
+    fn blocks_and_edges_follow_jumps_and_calls() {
+        // Branch past a call to a subroutine, with an unreachable dynamic jump at the end
+        let code = [6, 1, 6, 9, 5, 6, 10, 33, 0, 0, 6, 4, 34, 8, 4];
+        assert_eq!(
+            dot(&code, OPSET),
+            concat!(
+                "digraph code {\n",
+                "    node [shape=box, fontname=monospace];\n",
+                "    b0 [label=\"0: PUSH 1\\l2: PUSH 9\\l4: JZ\\l\"];\n",
+                "    b5 [label=\"5: PUSH 10\\l7: CALL\\l\"];\n",
+                "    b8 [label=\"8: HALT\\l\"];\n",
+                "    b9 [label=\"9: HALT\\l\"];\n",
+                "    b10 [label=\"10: PUSH 4\\l12: RET\\l\"];\n",
+                "    b13 [label=\"13: DUP\\l14: JUMP\\l\", style=dashed, fontcolor=gray, ",
+                "color=gray, peripheries=2, color=red];\n",
+                "    b0 -> b9;\n",
+                "    b0 -> b5;\n",
+                "    b5 -> b10;\n",
+                "    b5 -> b8;\n",
+                "    d13 [label=\"?\", shape=circle, color=red];\n",
+                "    b13 -> d13 [style=dashed, color=red];\n",
+                "}\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use flow::*;
+use ops::*;
+
+use std::fmt;
+
+/**
+Resources consumed along a path. Every instruction takes one unit of gas, while mem is charged
+the summed gas costs times the size of the running snapshot.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Cost {
+    pub steps: u64,
+    pub weight: u64,
+}
+
+impl Cost {
+    fn add(self, other: Cost) -> Cost {
+        Cost {
+            steps: self.steps + other.steps,
+            weight: self.weight + other.weight,
+        }
+    }
+
+    fn max(self, other: Cost) -> Cost {
+        Cost {
+            steps: self.steps.max(other.steps),
+            weight: self.weight.max(other.weight),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub blocks: Vec<u64>,
+    pub iteration: Cost,
+    pub exits: Vec<u64>,
+}
+
+impl Loop {
+    /**
+    A loop that no edge leaves can only be stopped by running out of resources
+    */
+    pub fn unbounded(&self) -> bool {
+        self.exits.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub loops: Vec<Loop>,
+    pub regions: Vec<(u64, Cost)>,
+    pub total: Option<Cost>,
+    pub max_depth: Option<u64>,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.total {
+            Some(total) => writeln!(
+                f,
+                "worst case: {} steps, weight {}",
+                total.steps, total.weight
+            )?,
+            None => writeln!(f, "worst case: unknown, code has loops or dynamic jumps")?,
+        }
+        match self.max_depth {
+            Some(depth) => writeln!(f, "max stack depth: {}", depth)?,
+            None => writeln!(f, "max stack depth: unbounded")?,
+        }
+        for &(start, cost) in self.regions.iter() {
+            writeln!(
+                f,
+                "loop-free from {}: {} steps, weight {}",
+                start, cost.steps, cost.weight
+            )?;
+        }
+        for lp in self.loops.iter() {
+            let blocks: Vec<String> = lp.blocks.iter().map(|b| b.to_string()).collect();
+            let exits: Vec<String> = lp.exits.iter().map(|b| b.to_string()).collect();
+            writeln!(
+                f,
+                "loop at {}: {} steps, weight {} per iteration, {}",
+                blocks.join(","),
+                lp.iteration.steps,
+                lp.iteration.weight,
+                if lp.unbounded() {
+                    "unbounded".to_string()
+                } else {
+                    format!("exits to {}", exits.join(","))
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn block_cost(code: &[u64], set: u64, block: &Block) -> Cost {
+    let mut cost = Cost::default();
+    let mut ip = block.start as usize;
+    while ip < block.end as usize {
+        if let Some((_, reqs)) = decode(code, ip, set) {
+            cost.steps += 1;
+            cost.weight += reqs.gas_cost.max(0) as u64;
+        }
+        ip += length(code, ip, set);
+    }
+    cost
+}
+
+/**
+Stack depth when leaving a block entered with depth, and the highest depth reached inside it,
+None once RUN pushes the words a child sent, which can be any number from the set that
+lets children send on
+*/
+fn block_depth(code: &[u64], set: u64, block: &Block, depth: u64) -> Option<(u64, u64)> {
+    let mut depth = depth;
+    let mut peak = depth;
+    let mut ip = block.start as usize;
+    while ip < block.end as usize {
+        if let Some((i, reqs)) = decode(code, ip, set) {
+            if i == IS::RUN && delivers(set) {
+                return None;
+            }
+            depth = (depth as i64 + reqs.addtl_mem as i64).max(0) as u64;
+            peak = peak.max(depth);
+        }
+        ip += length(code, ip, set);
+    }
+    Some((depth, peak))
+}
+
+/**
+Estimate worst-case resource use of a code section entered at entry with depth values on the stack
+*/
+pub fn estimate(code: &[u64], set: u64, entry: u64, depth: u64) -> Estimate {
+    let graph = build_from(code, set, entry);
+    let n = graph.blocks.len();
+    let start = graph.block_at(entry);
+    let costs: Vec<Cost> = graph.blocks.iter().map(|b| block_cost(code, set, b)).collect();
+    let succs: Vec<Vec<usize>> = graph
+        .blocks
+        .iter()
+        .map(|b| b.succs.iter().filter_map(|s| graph.block_at(*s)).collect())
+        .collect();
+
+    let mut in_loop: Vec<bool> = vec![false; n];
+    let mut loops: Vec<Loop> = Vec::new();
+    for component in graph.components() {
+        let b = component[0];
+        if component.len() == 1 && !succs[b].contains(&b) {
+            continue;
+        }
+        let mut iteration = Cost::default();
+        let mut exits: Vec<u64> = Vec::new();
+        for &b in component.iter() {
+            in_loop[b] = true;
+            iteration = iteration.add(costs[b]);
+            for &s in succs[b].iter() {
+                if !component.contains(&s) {
+                    exits.push(graph.blocks[s].start);
+                }
+            }
+        }
+        exits.sort();
+        exits.dedup();
+        loops.push(Loop {
+            blocks: component.iter().map(|&b| graph.blocks[b].start).collect(),
+            iteration,
+            exits,
+        });
+    }
+    loops.sort_by_key(|lp| lp.blocks[0]);
+
+    // Worst case from a block until the code ends, enters a loop or jumps dynamically
+    fn worst(
+        b: usize,
+        costs: &[Cost],
+        succs: &[Vec<usize>],
+        in_loop: &[bool],
+        memo: &mut Vec<Option<Cost>>,
+    ) -> Cost {
+        if let Some(cost) = memo[b] {
+            return cost;
+        }
+        let mut tail = Cost::default();
+        for &s in succs[b].iter() {
+            if !in_loop[s] {
+                tail = tail.max(worst(s, costs, succs, in_loop, memo));
+            }
+        }
+        let cost = costs[b].add(tail);
+        memo[b] = Some(cost);
+        cost
+    }
+
+    let mut memo: Vec<Option<Cost>> = vec![None; n];
+    let mut entries: Vec<usize> = Vec::new();
+    if let Some(b) = start.filter(|b| !in_loop[*b]) {
+        entries.push(b);
+    }
+    for lp in loops.iter() {
+        for exit in lp.exits.iter() {
+            let b = graph.block_at(*exit).unwrap();
+            if !in_loop[b] && !entries.contains(&b) {
+                entries.push(b);
+            }
+        }
+    }
+    entries.sort();
+    let regions: Vec<(u64, Cost)> = entries
+        .iter()
+        .map(|&b| {
+            (
+                graph.blocks[b].start,
+                worst(b, &costs, &succs, &in_loop, &mut memo),
+            )
+        })
+        .collect();
+
+    let open = graph
+        .blocks
+        .iter()
+        .any(|b| b.reachable && b.dynamic);
+    let total = match start {
+        None => Some(Cost::default()),
+        Some(b) if loops.is_empty() && !open => Some(worst(b, &costs, &succs, &in_loop, &mut memo)),
+        Some(_) => None,
+    };
+
+    // Highest entry depth per block, giving up once a block keeps growing
+    let mut max_depth: Option<u64> = Some(depth);
+    let mut entry: Vec<Option<u64>> = vec![None; n];
+    let mut updates: Vec<usize> = vec![0; n];
+    let mut work: Vec<(usize, u64)> = Vec::new();
+    if let Some(b) = start {
+        work.push((b, depth));
+    }
+    while let Some((b, d)) = work.pop() {
+        match entry[b] {
+            Some(known) if known >= d => continue,
+            _ => entry[b] = Some(d),
+        }
+        updates[b] += 1;
+        if updates[b] > n + 1 {
+            max_depth = None;
+            break;
+        }
+        let (exit, peak) = match block_depth(code, set, &graph.blocks[b], d) {
+            Some(depths) => depths,
+            None => {
+                max_depth = None;
+                break;
+            }
+        };
+        max_depth = max_depth.map(|m| m.max(peak));
+        for &s in succs[b].iter() {
+            work.push((s, exit));
+        }
+    }
+
+    Estimate {
+        loops,
+        regions,
+        total,
+        max_depth,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PUSH 0, JUMP loops forever, followed by PUSH 1, PUSH 2, ADD, HALT
+    const CODE: [u64; 9] = [6, 0, 4, 6, 1, 6, 2, 23, 0];
+
+    #[test]
+    fn entry_before_the_loop_is_unbounded() {
+        let estimate = estimate(&CODE, OPSET, 0, 0);
+        assert_eq!(estimate.total, None);
+        assert!(estimate.loops.iter().any(|lp| lp.unbounded()));
+    }
+
+    #[test]
+    fn resumed_process_is_estimated_from_its_ip() {
+        let estimate = estimate(&CODE, OPSET, 7, 2);
+        assert_eq!(estimate.total.map(|cost| cost.steps), Some(2));
+        assert!(estimate.loops.is_empty());
+        assert_eq!(estimate.max_depth, Some(2));
+    }
+
+    #[test]
+    fn depth_after_run_is_unbounded() {
+        // PUSH 0, PUSH 1, PUSH 2, RUN, HALT
+        let estimate = estimate(&[6, 0, 6, 1, 6, 2, 3, 0], OPSET, 0, 0);
+        assert_eq!(estimate.total.map(|cost| cost.steps), Some(5));
+        assert_eq!(estimate.max_depth, None);
+
+        // Before children could send, RUN only pops its arguments
+        let older = super::estimate(&[6, 0, 6, 1, 6, 2, 3, 0], opset(IS::SEND) - 1, 0, 0);
+        assert_eq!(older.max_depth, Some(3));
+    }
+}
@@ -0,0 +1,271 @@
+use ops::*;
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: u64,
+    pub end: u64,
+    pub succs: Vec<u64>,
+    pub dynamic: bool,
+    pub reachable: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Graph {
+    pub blocks: Vec<Block>,
+}
+
+/**
+Decode the instruction at ip, if it is known and fits into the code section
+*/
+pub fn decode(code: &[u64], ip: usize, set: u64) -> Option<(IS, Requirement)> {
+    let decoded = lookup(code[ip], set)?;
+    let reqs = requirement_in(decoded, set);
+    if ip + reqs.length as usize > code.len() {
+        return None;
+    }
+    Some((decoded, reqs))
+}
+
+/**
+Mark the instruction boundaries of a code section with a linear sweep
+*/
+pub fn starts(code: &[u64], set: u64) -> Vec<bool> {
+    let mut starts: Vec<bool> = vec![false; code.len()];
+    let mut ip: usize = 0;
+    while ip < code.len() {
+        starts[ip] = true;
+        ip += length(code, ip, set);
+    }
+    starts
+}
+
+/**
+Number of code words taken by the instruction at ip
+*/
+pub fn length(code: &[u64], ip: usize, set: u64) -> usize {
+    match lookup(code[ip], set) {
+        Some(i) => requirement(i).length as usize,
+        None => 1,
+    }
+}
+
+/**
+Jump target pushed by the instruction right before ip, if any
+*/
+pub fn static_target(code: &[u64], starts: &[bool], ip: usize) -> Option<u64> {
+    if ip >= 2 && starts[ip - 2] && code[ip - 2] == IS::PUSH as u64 {
+        Some(code[ip - 1])
+    } else {
+        None
+    }
+}
+
+/**
+Split a code section into basic blocks and link them by their possible successors
+*/
+pub fn build(code: &[u64], set: u64) -> Graph {
+    build_from(code, set, 0)
+}
+
+/**
+Like build, with blocks reachable from entry instead of ip 0
+*/
+pub fn build_from(code: &[u64], set: u64, entry: u64) -> Graph {
+    let starts = starts(code, set);
+    let on_boundary = |target: u64| target < code.len() as u64 && starts[target as usize];
+
+    // Every instruction after a control transfer and every resolved target begins a block
+    let mut leaders: Vec<bool> = vec![false; code.len()];
+    if !code.is_empty() {
+        leaders[0] = true;
+    }
+    if on_boundary(entry) {
+        leaders[entry as usize] = true;
+    }
+    for ip in 0..code.len() {
+        if !starts[ip] {
+            continue;
+        }
+        let next = (ip + length(code, ip, set)) as u64;
+        let i = match decode(code, ip, set) {
+            Some((i, _)) => i,
+            None => {
+                if on_boundary(next) {
+                    leaders[next as usize] = true;
+                }
+                continue;
+            }
+        };
+        match i {
+            IS::JUMP | IS::JZ | IS::CALL | IS::RET | IS::RETURN | IS::HALT | IS::YIELD
+            | IS::RUN | IS::SEND | IS::SENDAREA
+                if on_boundary(next) =>
+            {
+                leaders[next as usize] = true;
+            }
+            _ => {}
+        }
+        if i == IS::JUMP || i == IS::JZ || i == IS::CALL {
+            if let Some(target) = static_target(code, &starts, ip).filter(|t| on_boundary(*t)) {
+                leaders[target as usize] = true;
+            }
+        }
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut start: usize = 0;
+    let mut ip: usize = 0;
+    let mut succs: Vec<u64> = Vec::new();
+    let mut dynamic = false;
+    while ip < code.len() {
+        let decoded = decode(code, ip, set);
+        let next = (ip + length(code, ip, set)).min(code.len());
+
+        let mut falls = true;
+        let ends = match decoded {
+            None => {
+                falls = false;
+                true
+            }
+            Some((IS::HALT, _)) | Some((IS::RETURN, _)) | Some((IS::RET, _)) => {
+                falls = false;
+                true
+            }
+            Some((i @ IS::JUMP, _)) | Some((i @ IS::JZ, _)) | Some((i @ IS::CALL, _)) => {
+                falls = i != IS::JUMP;
+                match static_target(code, &starts, ip) {
+                    Some(target) if on_boundary(target) => succs.push(target),
+                    _ => dynamic = true,
+                }
+                true
+            }
+            Some((IS::YIELD, _)) | Some((IS::RUN, _)) | Some((IS::SEND, _))
+            | Some((IS::SENDAREA, _)) => true,
+            Some(_) => false,
+        };
+
+        if ends || next >= code.len() || leaders[next] {
+            if falls && on_boundary(next as u64) {
+                succs.push(next as u64);
+            }
+            succs.dedup();
+            blocks.push(Block {
+                start: start as u64,
+                end: next as u64,
+                succs,
+                dynamic,
+                reachable: false,
+            });
+            succs = Vec::new();
+            dynamic = false;
+            start = next;
+        }
+        ip = next;
+    }
+
+    let mut graph = Graph { blocks };
+    let mut work: Vec<usize> = graph.block_at(entry).into_iter().collect();
+    while let Some(b) = work.pop() {
+        if graph.blocks[b].reachable {
+            continue;
+        }
+        graph.blocks[b].reachable = true;
+        for succ in graph.blocks[b].succs.clone() {
+            if let Some(s) = graph.block_at(succ) {
+                work.push(s);
+            }
+        }
+    }
+    graph
+}
+
+impl Graph {
+    /**
+    Index of the block starting exactly at ip
+    */
+    pub fn block_at(&self, ip: u64) -> Option<usize> {
+        self.blocks.binary_search_by_key(&ip, |b| b.start).ok()
+    }
+
+    /**
+    Index of the block containing ip
+    */
+    #[allow(dead_code)]
+    pub fn block_of(&self, ip: u64) -> Option<usize> {
+        match self.blocks.binary_search_by_key(&ip, |b| b.start) {
+            Ok(b) => Some(b),
+            Err(0) => None,
+            Err(b) if ip < self.blocks[b - 1].end => Some(b - 1),
+            Err(_) => None,
+        }
+    }
+
+    /**
+    Strongly connected components among reachable blocks, as lists of block indices
+    */
+    pub fn components(&self) -> Vec<Vec<usize>> {
+        // Iterative Tarjan
+        let n = self.blocks.len();
+        let mut index: Vec<Option<usize>> = vec![None; n];
+        let mut low: Vec<usize> = vec![0; n];
+        let mut onstack: Vec<bool> = vec![false; n];
+        let mut stack: Vec<usize> = Vec::new();
+        let mut components: Vec<Vec<usize>> = Vec::new();
+        let mut counter = 0;
+
+        for root in 0..n {
+            if index[root].is_some() || !self.blocks[root].reachable {
+                continue;
+            }
+            let mut calls: Vec<(usize, usize)> = vec![(root, 0)];
+            while let Some((v, edge)) = calls.pop() {
+                if edge == 0 {
+                    index[v] = Some(counter);
+                    low[v] = counter;
+                    counter += 1;
+                    stack.push(v);
+                    onstack[v] = true;
+                }
+                let succs: Vec<usize> = self.blocks[v]
+                    .succs
+                    .iter()
+                    .filter_map(|s| self.block_at(*s))
+                    .collect();
+                if edge > 0 {
+                    let w = succs[edge - 1];
+                    low[v] = low[v].min(low[w]);
+                }
+                let mut descended = false;
+                for (e, &w) in succs.iter().enumerate().skip(edge) {
+                    match index[w] {
+                        None => {
+                            calls.push((v, e + 1));
+                            calls.push((w, 0));
+                            descended = true;
+                            break;
+                        }
+                        Some(wi) if onstack[w] => low[v] = low[v].min(wi),
+                        _ => {}
+                    }
+                }
+                if descended {
+                    continue;
+                }
+                if Some(low[v]) == index[v] {
+                    let mut component: Vec<usize> = Vec::new();
+                    loop {
+                        let w = stack.pop().unwrap();
+                        onstack[w] = false;
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    components.push(component);
+                }
+            }
+        }
+        components
+    }
+}
@@ -1,4 +1,15 @@
+use area::*;
+use compact::*;
+use compress::*;
+use store::*;
+use ops::num_traits::FromPrimitive;
+
+use byteorder::{ByteOrder, WriteBytesExt};
+use std::io;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
 #[derive(Debug, num_derive::FromPrimitive)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Stati {
     NOR, //Normal
     HLT, //Halt
@@ -9,6 +20,15 @@ pub enum Stati {
     OOS, //OutOfStack
     OOM, //OutOfMemory
     UOC, //UnknownCode
+    INV, //Invalid
+    RSU, //ReturnStackUnderflow
+    RSO, //ReturnStackOverflow
+    IVA, //InvalidArea
+    IVO, //InvalidOffset
+    ROA, //ReadOnlyArea
+    KIL, //Killed
+    DEP, //DepthExceeded
+    OOG, //OutOfGas
 }
 
 #[derive(Debug, Clone)]
@@ -20,84 +40,607 @@ pub struct Header {
     pub ip: u64,
 }
 
+// An area of the parent lent to a child while it runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Share {
+    pub area: u64,
+    pub parent: u64,
+    pub readonly: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Process {
     pub header: Header,
     pub code: Vec<u64>,
     pub stack: Vec<u64>,
     pub map: Vec<u64>,
-    pub memory: Vec<Vec<u64>>,
+    pub memory: Vec<Area>,
+    pub calls: Vec<u64>,
+    pub opset: u64,
+    pub fault: Option<(u64, u64)>,
+    pub shares: Vec<Share>,
+    pub outbox: Vec<u64>,
+    pub inbox: Vec<u64>,
 }
 
+// Offsets of the header fields and section lengths at the start of a snapshot,
+// a parent can access them directly in the area holding a child
+pub const STATUS: u64 = 0;
+pub const REC: u64 = 1;
+pub const GAS: u64 = 2;
+pub const MEM: u64 = 3;
+pub const IP: u64 = 4;
+pub const CODELEN: u64 = 5;
+pub const STACKLEN: u64 = 6;
+pub const MAPLEN: u64 = 7;
+pub const MEMORYLEN: u64 = 8;
+
+// Words before the code section
+pub const PREAMBLE: u64 = 9;
+
+// Set in the code length when the code section is packed, the remaining bits still give its
+// length in words
+pub const COMPACT: u64 = 1 << 63;
+
+// Set in the code length when the code section holds the hash of code kept in the shared store
+pub const REFERENCE: u64 = 1 << 62;
+
+pub const CODEFLAGS: u64 = COMPACT | REFERENCE;
+
+// Set in the length of an area stored sparsely, as the number of runs followed by the offset,
+// length and words of each run, all other words are zero
+pub const SPARSE: u64 = 1 << 63;
+
+// Optional sections after the memory areas, each stored as tag, length and data
+pub const CALLS: u64 = 1;
+pub const OPCODES: u64 = 2;
+pub const FAULT: u64 = 3;
+pub const SHARES: u64 = 4;
+pub const OUTBOX: u64 = 5;
+pub const INBOX: u64 = 6;
+
 /**
-Deserialize the standard process snapshot format to the internal representation
+Words a snapshot can be read from, a flat vector or an area holding a nested process
 */
-pub fn d(flat: &Vec<u64>) -> Process {
-    let header: Header = unsafe { std::ptr::read(flat.as_ptr() as *const _) };
-
-    const CODELEN: usize = 5;
-    let codelen: usize = flat[CODELEN] as usize;
-    let stacklen: usize = flat[CODELEN + 1] as usize;
-    let maplen: usize = flat[CODELEN + 2] as usize;
-    let memorylen: usize = flat[CODELEN + 3] as usize;
-
-    let mut start: usize = CODELEN + 4;
-    let mut end: usize = start + codelen;
-    let code: Vec<u64> = flat[start..end].to_vec();
-
-    start = end;
-    end = start + stacklen;
-    let mut stack: Vec<u64> = flat[start..end].to_vec();
-    stack.reserve(1024);
+pub trait Words {
+    fn size(&self) -> u64;
+    fn word(&self, index: u64) -> u64;
+    // Words backed by storage, which bounds how many are read one by one or copied
+    fn stored(&self) -> u64;
+    fn words(&self, start: u64, len: u64) -> Vec<u64>;
+    fn area(&self, start: u64, len: u64) -> Area;
+}
 
-    start = end;
-    end = start + maplen;
-    let mut map: Vec<u64> = flat[start..end].to_vec();
-    map.reserve(1024);
+impl Words for [u64] {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn word(&self, index: u64) -> u64 {
+        self[index as usize]
+    }
+
+    fn stored(&self) -> u64 {
+        self.len() as u64
+    }
 
-    end = end;
-    let mut memory: Vec<Vec<u64>> = Vec::new();
-    for _area in 0..memorylen {
-        let arealen: usize = flat[end] as usize;
-        start = end + 1;
-        end = start + arealen;
-        let mut areavec = flat[start..end].to_vec();
-        areavec.reserve(4096);
-        memory.push(areavec);
-    }
-
-    return Process {
-        header: header,
-        code: code,
-        stack: stack,
-        map: map,
-        memory: memory,
+    fn words(&self, start: u64, len: u64) -> Vec<u64> {
+        self[start as usize..(start + len) as usize].to_vec()
+    }
+
+    fn area(&self, start: u64, len: u64) -> Area {
+        Area::from(self.words(start, len))
+    }
+}
+
+impl Words for Area {
+    fn size(&self) -> u64 {
+        self.len()
+    }
+
+    fn word(&self, index: u64) -> u64 {
+        self.get(index)
+    }
+
+    fn stored(&self) -> u64 {
+        self.allocated()
+    }
+
+    fn words(&self, start: u64, len: u64) -> Vec<u64> {
+        self.read(start, len)
+    }
+
+    fn area(&self, start: u64, len: u64) -> Area {
+        self.slice(start, len)
+    }
+}
+
+/**
+Deserialize words only if they are laid out like a process snapshot, for telling apart
+areas that hold nested processes from other data. Code referenced by hash is rejected.
+*/
+pub fn try_d(flat: &[u64]) -> Option<Process> {
+    parse(flat, &CodeStore::new())
+}
+
+/**
+Like try_d, with code referenced by hash taken from codes, rejecting unknown hashes
+*/
+pub fn try_d_with(flat: &[u64], codes: &CodeStore) -> Option<Process> {
+    parse(flat, codes)
+}
+
+/**
+Like try_d, reading the process an area holds without materializing the area
+*/
+pub fn try_d_area(area: &Area) -> Option<Process> {
+    parse(area, &CodeStore::new())
+}
+
+/**
+Check and lay out a snapshot. Every word outside of dense areas is read one by one or copied,
+so their number may not exceed the stored words: an area declaring a huge length but storing
+little is rejected before anything proportional to its length is allocated.
+*/
+fn parse<W: Words + ?Sized>(flat: &W, codes: &CodeStore) -> Option<Process> {
+    let size = flat.size();
+    let stored = flat.stored();
+    if size < PREAMBLE {
+        return None;
+    }
+    let mut read: u64 = PREAMBLE;
+
+    let codeflags = flat.word(CODELEN) & CODEFLAGS;
+    let codelen = flat.word(CODELEN) & !CODEFLAGS;
+    let mut end: u64 = PREAMBLE.checked_add(codelen)?;
+    read = read.checked_add(codelen)?;
+    if end > size || read > stored {
+        return None;
+    }
+    let code: Vec<u64> = if codeflags == CODEFLAGS {
+        return None;
+    } else if codeflags == COMPACT {
+        unpack(&flat.words(PREAMBLE, codelen))?
+    } else if codeflags == REFERENCE {
+        if codelen != 4 {
+            return None;
+        }
+        let key = reference(&flat.words(PREAMBLE, codelen));
+        codes.get(&key)?.clone()
+    } else {
+        flat.words(PREAMBLE, codelen)
+    };
+
+    let mut dense = |end: &mut u64, len: u64| -> Option<Vec<u64>> {
+        let start = *end;
+        *end = start.checked_add(len)?;
+        read = read.checked_add(len)?;
+        if *end > size || read > stored {
+            return None;
+        }
+        Some(flat.words(start, len))
     };
+    let mut stack = dense(&mut end, flat.word(STACKLEN))?;
+    stack.reserve(1024);
+    let mut map = dense(&mut end, flat.word(MAPLEN))?;
+    map.reserve(1024);
+
+    let mut memory: Vec<Area> = Vec::new();
+    for _area in 0..flat.word(MEMORYLEN) {
+        let arealen = dense(&mut end, 1)?[0];
+        if arealen & SPARSE == 0 {
+            let start = end;
+            end = end.checked_add(arealen)?;
+            if end > size {
+                return None;
+            }
+            memory.push(flat.area(start, arealen));
+            continue;
+        }
+        let mut area = Area::new();
+        area.resize(arealen & !SPARSE);
+        for _run in 0..dense(&mut end, 1)?[0] {
+            let run = dense(&mut end, 2)?;
+            if run[0].checked_add(run[1])? > area.len() {
+                return None;
+            }
+            area.write(run[0], &dense(&mut end, run[1])?);
+        }
+        memory.push(area);
+    }
+
+    if Stati::from_u64(flat.word(STATUS)).is_none() || flat.word(REC) > flat.word(MEMORYLEN) {
+        return None;
+    }
+
+    // Sections are written in tag order, at most once and only when not empty
+    let mut calls: Vec<u64> = Vec::new();
+    let mut opset: u64 = 0;
+    let mut fault: Option<(u64, u64)> = None;
+    let mut shares: Vec<Share> = Vec::new();
+    let mut outbox: Vec<u64> = Vec::new();
+    let mut inbox: Vec<u64> = Vec::new();
+    let mut last: u64 = 0;
+    while end < size {
+        let head = dense(&mut end, 2)?;
+        let (tag, sectionlen) = (head[0], head[1]);
+        let fits = match tag {
+            OPCODES => sectionlen == 1,
+            FAULT => sectionlen == 2,
+            SHARES => sectionlen > 0 && sectionlen.is_multiple_of(3),
+            CALLS | OUTBOX | INBOX => sectionlen > 0,
+            _ => false,
+        };
+        if !fits || tag <= last {
+            return None;
+        }
+        last = tag;
+        let section = dense(&mut end, sectionlen)?;
+        match tag {
+            CALLS => calls = section,
+            OPCODES => opset = section[0],
+            FAULT => fault = Some((section[0], section[1])),
+            SHARES => {
+                for share in section.chunks(3) {
+                    shares.push(Share {
+                        area: share[0],
+                        parent: share[1],
+                        readonly: share[2] != 0,
+                    });
+                }
+            }
+            OUTBOX => outbox = section,
+            _ => inbox = section,
+        }
+    }
+
+    Some(Process {
+        header: Header {
+            status: flat.word(STATUS),
+            rec: flat.word(REC),
+            gas: flat.word(GAS),
+            mem: flat.word(MEM),
+            ip: flat.word(IP),
+        },
+        code,
+        stack,
+        map,
+        memory,
+        calls,
+        opset,
+        fault,
+        shares,
+        outbox,
+        inbox,
+    })
+}
+
+fn reference(words: &[u64]) -> Hash {
+    let mut key: Hash = [0; 4];
+    key.copy_from_slice(&words[..4]);
+    key
+}
+
+/**
+Deserialize the standard process snapshot format to the internal representation, for words
+known to hold a valid snapshot such as the ones s writes. Panics on anything try_d rejects.
+*/
+pub fn d(flat: &[u64]) -> Process {
+    try_d(flat).expect("Not a valid snapshot")
 }
 
 /**
 Serialize the internal representation to the standard process snapshot format
 */
 pub fn s(sharp: &Process) -> Vec<u64> {
-    let mut flat: Vec<u64> = Vec::new();
-    flat.push(sharp.header.status);
-    flat.push(sharp.header.rec);
-    flat.push(sharp.header.gas);
-    flat.push(sharp.header.mem);
-    flat.push(sharp.header.ip);
-
-    flat.push(sharp.code.len() as u64);
-    flat.push(sharp.stack.len() as u64);
-    flat.push(sharp.map.len() as u64);
-    flat.push(sharp.memory.len() as u64);
+    let mut flat: Vec<u64> = vec![
+        sharp.header.status,
+        sharp.header.rec,
+        sharp.header.gas,
+        sharp.header.mem,
+        sharp.header.ip,
+        sharp.code.len() as u64,
+        sharp.stack.len() as u64,
+        sharp.map.len() as u64,
+        sharp.memory.len() as u64,
+    ];
 
     flat.extend(&sharp.code);
     flat.extend(&sharp.stack);
     flat.extend(&sharp.map);
 
+    // Areas that are mostly unwritten are stored sparsely, so they are never materialized
     for area in sharp.memory.iter() {
-        flat.push(area.len() as u64);
-        flat.extend(area.clone());
+        let runs = area.runs();
+        let stored: u64 = runs.iter().map(|run| 2 + run.1.len() as u64).sum();
+        if 1 + stored < area.len() {
+            flat.push(SPARSE | area.len());
+            flat.push(runs.len() as u64);
+            for (offset, words) in runs {
+                flat.push(offset);
+                flat.push(words.len() as u64);
+                flat.extend(words);
+            }
+        } else {
+            flat.push(area.len());
+            flat.extend(area.to_vec());
+        }
+    }
+
+    sections(sharp, &mut flat);
+    flat
+}
+
+/**
+Words the process takes with every area stored in full, which running it is charged for
+*/
+pub fn size(sharp: &Process) -> u64 {
+    let mut tail: Vec<u64> = Vec::new();
+    sections(sharp, &mut tail);
+    let areas: u64 = sharp.memory.iter().map(|area| 1 + area.len()).sum();
+    (PREAMBLE + (sharp.code.len() + sharp.stack.len() + sharp.map.len() + tail.len()) as u64)
+        .saturating_add(areas)
+}
+
+fn sections(sharp: &Process, flat: &mut Vec<u64>) {
+    if !sharp.calls.is_empty() {
+        flat.push(CALLS);
+        flat.push(sharp.calls.len() as u64);
+        flat.extend(&sharp.calls);
+    }
+
+    if sharp.opset != 0 {
+        flat.push(OPCODES);
+        flat.push(1);
+        flat.push(sharp.opset);
+    }
+
+    if let Some((area, offset)) = sharp.fault {
+        flat.push(FAULT);
+        flat.push(2);
+        flat.push(area);
+        flat.push(offset);
+    }
+
+    if !sharp.shares.is_empty() {
+        flat.push(SHARES);
+        flat.push(sharp.shares.len() as u64 * 3);
+        for share in sharp.shares.iter() {
+            flat.push(share.area);
+            flat.push(share.parent);
+            flat.push(share.readonly as u64);
+        }
+    }
+
+    if !sharp.outbox.is_empty() {
+        flat.push(OUTBOX);
+        flat.push(sharp.outbox.len() as u64);
+        flat.extend(&sharp.outbox);
+    }
+
+    if !sharp.inbox.is_empty() {
+        flat.push(INBOX);
+        flat.push(sharp.inbox.len() as u64);
+        flat.extend(&sharp.inbox);
+    }
+}
+
+/**
+Serialize like s, but with the code section packed
+*/
+pub fn s_compact(sharp: &Process) -> Vec<u64> {
+    let mut flat = s(sharp);
+    let packed = pack(&sharp.code);
+    let start = PREAMBLE as usize;
+    flat[CODELEN as usize] = COMPACT | packed.len() as u64;
+    flat.splice(start..start + sharp.code.len(), packed);
+    flat
+}
+
+/**
+Serialize like s, but with the code added to codes and referenced by its hash
+*/
+pub fn s_referenced(sharp: &Process, codes: &mut CodeStore) -> Vec<u64> {
+    let mut flat = s(sharp);
+    let key = codes.insert(&sharp.code);
+    let start = PREAMBLE as usize;
+    flat[CODELEN as usize] = REFERENCE | key.len() as u64;
+    flat.splice(start..start + sharp.code.len(), key.iter().cloned());
+    flat
+}
+
+/**
+Read words of the given byte order until the end of the stream, straight into the result
+*/
+pub fn read_words<B: ByteOrder, R: Read>(reader: R) -> io::Result<Vec<u64>> {
+    let mut reader = BufReader::new(reader);
+    let mut flat: Vec<u64> = Vec::new();
+    loop {
+        let mut word = [0u8; 8];
+        let mut filled = 0;
+        while filled < 8 {
+            match reader.read(&mut word[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match filled {
+            0 => return Ok(flat),
+            8 => flat.push(B::read_u64(&word)),
+            _ => return Err(io::Error::new(ErrorKind::UnexpectedEof, "stream ends inside a word")),
+        }
+    }
+}
+
+/**
+Write words in the given byte order
+*/
+pub fn write_words<B: ByteOrder, W: Write>(writer: W, flat: &[u64]) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    for word in flat.iter() {
+        writer.write_u64::<B>(*word)?;
+    }
+    writer.flush()
+}
+
+/**
+Read a snapshot, compressed or not, and check that it is well-formed, with code referenced by
+hash taken from codes
+*/
+pub fn read_snapshot<B: ByteOrder, R: Read>(reader: R, codes: &CodeStore) -> io::Result<Process> {
+    let flat = decompress(read_words::<B, R>(reader)?)
+        .map_err(|message| io::Error::new(ErrorKind::InvalidData, message))?;
+    try_d_with(&flat, codes)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "not a valid snapshot"))
+}
+
+/**
+Write a snapshot in the standard format, compressed with codec if one is given
+*/
+pub fn write_snapshot<B: ByteOrder, W: Write>(
+    writer: W,
+    sharp: &Process,
+    codec: Option<u64>,
+) -> io::Result<()> {
+    let flat = match codec {
+        Some(codec) => compress(&s(sharp), codec)
+            .map_err(|message| io::Error::new(ErrorKind::InvalidInput, message))?,
+        None => s(sharp),
+    };
+    write_words::<B, W>(writer, &flat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(code: &[u64]) -> Process {
+        let mut flat = vec![0, 0, 0, 0, 0, code.len() as u64, 0, 0, 0];
+        flat.extend(code);
+        d(&flat)
+    }
+
+    #[test]
+    fn sparse_areas_are_stored_without_their_zeros() {
+        let mut sharp = process(&[0]);
+        let mut area = Area::new();
+        area.resize(1 << 40);
+        area.set(5, 7);
+        area.set((1 << 40) - 1, 9);
+        sharp.memory.push(area);
+        sharp.memory.push(Area::from(vec![1, 0, 2]));
+
+        let flat = s(&sharp);
+        assert!(flat.len() < 3000);
+        assert_eq!(size(&sharp), PREAMBLE + 1 + (1 + (1 << 40)) + (1 + 3));
+
+        let back = try_d(&flat).unwrap();
+        assert_eq!(back.memory[0].len(), 1 << 40);
+        assert_eq!(back.memory[0].get(5), 7);
+        assert_eq!(back.memory[0].get((1 << 40) - 1), 9);
+        assert_eq!(back.memory[0].get(6), 0);
+        assert_eq!(back.memory[1].to_vec(), vec![1, 0, 2]);
+        assert_eq!(s(&back), flat);
+    }
+
+    #[test]
+    fn sparse_runs_past_the_length_are_rejected() {
+        let mut sharp = process(&[0]);
+        let mut area = Area::new();
+        area.resize(1 << 20);
+        area.set(3, 1);
+        sharp.memory.push(area);
+        let mut flat = s(&sharp);
+        // Offset of the only run
+        flat[PREAMBLE as usize + 3] = 1 << 20;
+        assert!(try_d(&flat).is_none());
+    }
+
+    #[test]
+    fn compact_code_round_trips() {
+        let sharp = process(&[6, 1 << 40, 6, 2, 23, 0]);
+        let flat = s_compact(&sharp);
+        assert!(flat[CODELEN as usize] & COMPACT != 0);
+        assert_eq!(try_d(&flat).unwrap().code, sharp.code);
+    }
+
+    #[test]
+    fn malformed_compact_code_is_rejected() {
+        // Claims 100 bytes of code in a single word
+        let flat = vec![0, 0, 0, 0, 0, COMPACT | 2, 0, 0, 0, 100, 0];
+        assert!(try_d(&flat).is_none());
+    }
+
+    #[test]
+    fn referenced_code_needs_its_store() {
+        let sharp = process(&[6, 1, 0]);
+        let mut codes = CodeStore::new();
+        let flat = s_referenced(&sharp, &mut codes);
+        assert_eq!(flat[CODELEN as usize], REFERENCE | 4);
+        assert_eq!(try_d_with(&flat, &codes).unwrap().code, sharp.code);
+        assert!(try_d(&flat).is_none());
+        assert!(try_d_with(&flat, &CodeStore::new()).is_none());
+    }
+
+    #[test]
+    fn areas_are_read_like_their_words() {
+        let mut sharp = process(&[6, 1, 0]);
+        sharp.stack = vec![3; 1500];
+        let mut dense = Area::new();
+        dense.resize(3000);
+        dense.write(1020, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        dense.set(2999, 9);
+        sharp.memory.push(dense);
+        let mut sparse = Area::new();
+        sparse.resize(1 << 40);
+        sparse.set(5000, 1);
+        sharp.memory.push(sparse);
+        sharp.outbox = vec![4, 5];
+
+        let flat = s(&sharp);
+        let read = try_d_area(&Area::from(flat.clone())).unwrap();
+        assert_eq!(s(&read), flat);
+    }
+
+    #[test]
+    fn areas_storing_less_than_they_declare_are_not_read() {
+        let mut area = Area::from(vec![0, 0, 0, 0, 0, 0, 1 << 40, 0, 0]);
+        area.resize(PREAMBLE + (1 << 40));
+        assert!(try_d_area(&area).is_none());
+    }
+
+    #[test]
+    fn words_read_back_in_either_byte_order() {
+        use byteorder::{BigEndian, LittleEndian};
+        let flat = vec![0, 1, 0x0102_0304_0506_0708, u64::MAX];
+
+        let mut big: Vec<u8> = Vec::new();
+        write_words::<BigEndian, _>(&mut big, &flat).unwrap();
+        assert_eq!(big.len(), 32);
+        assert_eq!(&big[16..24], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(read_words::<BigEndian, _>(&big[..]).unwrap(), flat);
+
+        let mut little: Vec<u8> = Vec::new();
+        write_words::<LittleEndian, _>(&mut little, &flat).unwrap();
+        assert_eq!(&little[16..24], &[8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(read_words::<LittleEndian, _>(&little[..]).unwrap(), flat);
+
+        // Words split across reads are put back together
+        let split = (&big[..5]).chain(&big[5..]);
+        assert_eq!(read_words::<BigEndian, _>(split).unwrap(), flat);
+        assert_eq!(read_words::<BigEndian, _>(&[][..]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn partial_words_are_an_error() {
+        use byteorder::BigEndian;
+        let mut bytes: Vec<u8> = Vec::new();
+        write_words::<BigEndian, _>(&mut bytes, &[1, 2]).unwrap();
+        for len in (1..16).filter(|len| len % 8 != 0) {
+            let error = read_words::<BigEndian, _>(&bytes[..len]).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        }
     }
-    return flat;
 }
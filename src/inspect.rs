@@ -0,0 +1,150 @@
+use ops::num_traits::FromPrimitive;
+use formats::*;
+use disasm::*;
+
+use std::fmt::Write;
+
+// Instructions disassembled from ip and words shown of stacks and data areas
+const PREVIEW: usize = 8;
+
+fn status(status: u64) -> String {
+    match Stati::from_u64(status) {
+        Some(name) => format!("{:?}", name),
+        None => format!("?{}", status),
+    }
+}
+
+fn preview(words: &[u64]) -> String {
+    let shown: Vec<String> = words.iter().take(PREVIEW).map(|w| w.to_string()).collect();
+    if words.len() > PREVIEW {
+        format!("[{}, ...]", shown.join(", "))
+    } else {
+        format!("[{}]", shown.join(", "))
+    }
+}
+
+fn process(sharp: &Process, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    writeln!(
+        out,
+        "{}process {} gas {} mem {} ip {} rec {} opset {}",
+        pad,
+        status(sharp.header.status),
+        sharp.header.gas,
+        sharp.header.mem,
+        sharp.header.ip,
+        sharp.header.rec,
+        sharp.opset
+    )
+    .unwrap();
+
+    writeln!(out, "{}  code {} words", pad, sharp.code.len()).unwrap();
+    let start = if sharp.header.ip < sharp.code.len() as u64 { sharp.header.ip } else { 0 };
+    for (ip, line) in range(&sharp.code, sharp.opset, start, sharp.code.len() as u64)
+        .iter()
+        .take(PREVIEW)
+    {
+        writeln!(out, "{}    {:>6}  {}", pad, ip, line).unwrap();
+    }
+
+    // Top of the stack first
+    let top: Vec<u64> = sharp.stack.iter().rev().cloned().collect();
+    writeln!(out, "{}  stack {} values, top first {}", pad, top.len(), preview(&top)).unwrap();
+
+    if !sharp.map.is_empty() {
+        let pairs: Vec<String> = sharp
+            .map
+            .chunks(2)
+            .map(|pair| format!("{}={}", pair[0], pair.get(1).cloned().unwrap_or(0)))
+            .collect();
+        writeln!(out, "{}  map {}", pad, pairs.join(" ")).unwrap();
+    }
+    if !sharp.calls.is_empty() {
+        writeln!(out, "{}  calls {}", pad, preview(&sharp.calls)).unwrap();
+    }
+    if let Some((area, offset)) = sharp.fault {
+        writeln!(out, "{}  fault at area {} offset {}", pad, area, offset).unwrap();
+    }
+    for share in sharp.shares.iter() {
+        writeln!(
+            out,
+            "{}  area {} shared from parent area {}{}",
+            pad,
+            share.area,
+            share.parent,
+            if share.readonly { " read-only" } else { "" }
+        )
+        .unwrap();
+    }
+    if !sharp.outbox.is_empty() {
+        writeln!(out, "{}  outbox {}", pad, preview(&sharp.outbox)).unwrap();
+    }
+    if !sharp.inbox.is_empty() {
+        writeln!(out, "{}  inbox {}", pad, preview(&sharp.inbox)).unwrap();
+    }
+
+    // Areas are read in place, so sparse ones cost what they store
+    for (index, area) in sharp.memory.iter().enumerate() {
+        match try_d_area(area) {
+            Some(child) => {
+                writeln!(out, "{}  area {}: {} words holding", pad, index, area.len()).unwrap();
+                process(&child, indent + 2, out);
+            }
+            None => {
+                let head = area.read(0, area.len().min(PREVIEW as u64 + 1));
+                writeln!(out, "{}  area {}: {} words {}", pad, index, area.len(), preview(&head))
+                    .unwrap();
+            }
+        }
+    }
+}
+
+/**
+Render a snapshot and every process nested in its areas as an indented tree
+*/
+pub fn inspect(sharp: &Process) -> String {
+    let mut out = String::new();
+    process(sharp, 0, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_processes_are_indented() {
+        // PUSH 5, HALT
+        let child = d(&[1, 0, 10, 20, 2, 3, 1, 0, 0, 6, 5, 0, 5]);
+        let mut flat = vec![0, 1, 100, 200, 0, 1, 2, 0, 2, 0, 7, 8];
+        flat.push(s(&child).len() as u64);
+        flat.extend(s(&child));
+        flat.extend(&[10, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        let expected = "\
+process NOR gas 100 mem 200 ip 0 rec 1 opset 0
+  code 1 words
+         0  HALT
+  stack 2 values, top first [8, 7]
+  area 0: 13 words holding
+    process HLT gas 10 mem 20 ip 2 rec 0 opset 0
+      code 3 words
+             2  HALT
+      stack 1 values, top first [5]
+  area 1: 10 words [1, 2, 3, 4, 5, 6, 7, 8, ...]
+";
+        assert_eq!(inspect(&d(&flat)), expected);
+    }
+
+    #[test]
+    fn huge_sparse_areas_are_summarized() {
+        let sharp = d(&[0, 0, 0, 0, 0, 1, 0, 0, 1, 0, SPARSE | (1 << 40), 0]);
+        let expected = "\
+process NOR gas 0 mem 0 ip 0 rec 0 opset 0
+  code 1 words
+         0  HALT
+  stack 0 values, top first []
+  area 0: 1099511627776 words [0, 0, 0, 0, 0, 0, 0, 0, ...]
+";
+        assert_eq!(inspect(&sharp), expected);
+    }
+}
@@ -1,22 +1,46 @@
 #![allow(while_true)]
-#![feature(nll)]
 
 use std::fs;
 extern crate byteorder;
-use byteorder::{ByteOrder, BigEndian};
+#[cfg(feature = "deflate")]
+extern crate flate2;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate sha2;
+extern crate toml;
+use byteorder::BigEndian;
 use std::io;
 use std::io::prelude::*;
 
 mod ops;
+mod area;
 mod formats;
 mod vm;
+mod observer;
 mod container;
+mod verify;
+mod flow;
+mod estimate;
+mod disasm;
+mod dot;
+mod profile;
+mod coverage;
+mod record;
+mod debugger;
+mod diff;
+mod inspect;
+mod merkle;
+mod text;
+mod compact;
+mod compress;
+mod store;
 
 fn print42(sharp: &mut formats::Process) {
     let ci: u32 = sharp.stack.pop().unwrap() as u32;
     let c = std::char::from_u32(ci).unwrap();
     print!("{}", c);
-    io::stdout().flush().ok().expect("Could not flush stdout");
+    io::stdout().flush().expect("Could not flush stdout");
 }
 
 fn write_hello_bin() {
@@ -51,21 +75,339 @@ fn write_hello_bin() {
         32, 18, 0,
     ];
 
-    let mut vec8: Vec<u8> = vec![0;hello.len()*8];
-    BigEndian::write_u64_into(&hello, &mut vec8);
+    println!("Writing hello.bin: {} bytes", hello.len() * 8);
+    write_words("hello.bin", &hello);
+}
+
+// Files are stored big-endian
+fn read_words(path: &str) -> Vec<u64> {
+    let file = fs::File::open(path).expect("Unable to read file");
+    formats::read_words::<BigEndian, _>(file).expect("Unable to read file")
+}
+
+fn write_words(path: &str, flat: &[u64]) {
+    let file = fs::File::create(path).expect("Unable to write file");
+    formats::write_words::<BigEndian, _>(file, flat).expect("Unable to write file");
+}
+
+fn save(path: &str, sharp: &formats::Process, codec: Option<u64>) {
+    let file = fs::File::create(path).expect("Unable to write file");
+    formats::write_snapshot::<BigEndian, _>(file, sharp, codec).expect("Unable to write file");
+}
+
+// Compressed snapshots are recognized and expanded before deserializing
+fn load(path: &str, codes: &store::CodeStore) -> formats::Process {
+    let file = fs::File::open(path).expect("Unable to read file");
+    match formats::read_snapshot::<BigEndian, _>(file, codes) {
+        Ok(sharp) => sharp,
+        Err(error) => {
+            println!("{}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+}
 
-    println!("Writing hello.bin: {} bytes", vec8.len());
-    fs::write("hello.bin", vec8).expect("Writing hello.bin failed");
+// A missing store file counts as empty
+fn load_store(path: &str) -> store::CodeStore {
+    if fs::metadata(path).is_ok() {
+        store::CodeStore::from_words(&read_words(path))
+    } else {
+        store::CodeStore::new()
+    }
+}
+
+/**
+Step a process back and forth from commands on stdin:
+s [n] steps forward, b [n] steps back, g n goes to an instruction count, q quits
+*/
+fn debug(sharp: formats::Process, recording: record::Recording) {
+    let mut debugger = debugger::Debugger::new(sharp, recording);
+    let stdin = io::stdin();
+    loop {
+        let (depth, active) = debugger.active();
+        let ip = active.header.ip;
+        let instr = if ip < active.code.len() as u64 {
+            disasm::instruction(&active.code, ip as usize, active.opset)
+        } else {
+            String::new()
+        };
+        println!(
+            "{} depth {} status {} ip {} {} stack {:?}",
+            debugger.position, depth, active.header.status, ip, instr, active.stack
+        );
+        print!("> ");
+        io::stdout().flush().expect("Could not flush stdout");
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count: u64 = words.get(1).and_then(|w| w.parse().ok()).unwrap_or(1);
+        match words.first() {
+            Some(&"s") => {
+                let target = debugger.position + count;
+                debugger.seek(target);
+            }
+            Some(&"b") => {
+                let target = debugger.position.saturating_sub(count);
+                debugger.seek(target);
+            }
+            Some(&"g") => debugger.seek(count),
+            Some(&"q") => break,
+            _ => {}
+        }
+    }
 }
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // Code referenced by hash is resolved from the store given before the command
+    let mut codes = store::CodeStore::new();
+    if args.len() > 2 && args[1] == "--store" {
+        codes = load_store(&args[2]);
+        args.drain(1..3);
+    }
+
+    // Snapshots written by commands are compressed with the codec given before the command
+    let mut codec: Option<u64> = None;
+    if args.len() > 2 && args[1] == "--compress" {
+        codec = Some(compress::codec(&args[2]).expect("Codec must be zeros or deflate"));
+        args.drain(1..3);
+    }
+
+    if args.len() == 3 && args[1] == "verify" {
+        let diagnostics = verify::verify_process(&load(&args[2], &codes));
+        for diagnostic in diagnostics.iter() {
+            println!("{}", diagnostic);
+        }
+        if !diagnostics.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.len() == 3 && args[1] == "estimate" {
+        let sharp = load(&args[2], &codes);
+        print!(
+            "{}",
+            estimate::estimate(
+                &sharp.code,
+                sharp.opset,
+                sharp.header.ip,
+                sharp.stack.len() as u64
+            )
+        );
+        return;
+    }
+
+    if args.len() == 3 && args[1] == "dot" {
+        let sharp = load(&args[2], &codes);
+        print!("{}", dot::dot(&sharp.code, sharp.opset));
+        return;
+    }
+
+    if (args.len() == 3 || args.len() == 4) && args[1] == "profile" {
+        let mut instance = container::Container::new(load(&args[2], &codes))
+            .with_observer(profile::Profile::new());
+        instance.add_func(42, print42);
+        instance.run_io();
+        if args.len() == 4 && args[3] == "collapsed" {
+            print!("{}", instance.observer().collapsed());
+        } else {
+            print!("{}", instance.observer().report());
+        }
+        return;
+    }
+
+    // Hit counts are added to the ones already in the optional coverage file
+    if (args.len() == 3 || args.len() == 4) && args[1] == "coverage" {
+        let mut instance = container::Container::new(load(&args[2], &codes))
+            .with_observer(coverage::Coverage::new());
+        instance.add_func(42, print42);
+        instance.run_io();
+        let mut coverage = instance.observer().clone();
+        if args.len() == 4 {
+            if fs::metadata(&args[3]).is_ok() {
+                let earlier = coverage::Coverage::from_words(&read_words(&args[3]));
+                coverage.merge(&earlier.expect("Not a coverage file"));
+            }
+            write_words(&args[3], &coverage.to_words());
+        }
+        print!("{}", coverage.report());
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "record" {
+        let mut instance = container::Container::new(load(&args[2], &codes));
+        instance.add_func(42, print42);
+        instance.record();
+        instance.run_io();
+        write_words(&args[3], &instance.recording().unwrap().to_words());
+        return;
+    }
+
+    // Host functions are not called, the recording supplies their effects
+    if args.len() == 4 && args[1] == "replay" {
+        let mut instance = container::Container::new(load(&args[2], &codes));
+        match record::Recording::from_words(&read_words(&args[3])) {
+            Some(recording) => instance.replay(recording),
+            None => {
+                println!("{}: not a recording", args[3]);
+                std::process::exit(1);
+            }
+        }
+        instance.run_io();
+        return;
+    }
+
+    if args.len() == 3 && args[1] == "totext" {
+        print!("{}", text::to_text(&load(&args[2], &codes)));
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "fromtext" {
+        let source = fs::read_to_string(&args[2]).expect("Unable to read file");
+        match text::from_text(&source) {
+            Ok(sharp) => save(&args[3], &sharp, codec),
+            Err(message) => {
+                println!("{}", message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "compact" {
+        write_words(&args[3], &formats::s_compact(&load(&args[2], &codes)));
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "expand" {
+        save(&args[3], &load(&args[2], &codes), codec);
+        return;
+    }
+
+    if (args.len() == 4 || args.len() == 5) && args[1] == "compress" {
+        let codec = if args.len() == 5 {
+            compress::codec(&args[4]).expect("Codec must be zeros or deflate")
+        } else {
+            compress::ZEROS
+        };
+        // Words are kept as stored so compact code sections stay compact
+        match compress::decompress(read_words(&args[2]))
+            .and_then(|flat| compress::compress(&flat, codec))
+        {
+            Ok(flat) => write_words(&args[3], &flat),
+            Err(message) => {
+                println!("{}", message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "decompress" {
+        match compress::decompress(read_words(&args[2])) {
+            Ok(flat) => write_words(&args[3], &flat),
+            Err(message) => {
+                println!("{}: {}", args[2], message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.len() == 5 && args[1] == "reference" {
+        let sharp = load(&args[2], &codes);
+        codes.merge(&load_store(&args[4]));
+        write_words(&args[3], &formats::s_referenced(&sharp, &mut codes));
+        write_words(&args[4], &codes.to_words());
+        return;
+    }
+
+    if args.len() == 3 && args[1] == "merkle" {
+        println!("{}", merkle::hex(&merkle::root(&load(&args[2], &codes))));
+        return;
+    }
+
+    if args.len() == 6 && args[1] == "prove" {
+        let sharp = load(&args[2], &codes);
+        let areas: Vec<u64> = args[3]
+            .split('.')
+            .map(|index| index.parse().expect("Areas must be numbers separated by dots"))
+            .collect();
+        let offset: u64 = args[4].parse().expect("Offset must be a number");
+        match merkle::prove(&sharp, &areas, offset) {
+            Some(proof) => {
+                write_words(&args[5], &proof.to_words());
+                println!("{}", merkle::hex(&merkle::root(&sharp)));
+            }
+            None => {
+                println!("No word at area {} offset {}", args[3], offset);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "check" {
+        let root = merkle::from_hex(&args[2]).expect("Root must be 64 hex digits");
+        match merkle::Proof::from_words(&read_words(&args[3])) {
+            Some(ref proof) if proof.verify(&root) => {
+                let areas: Vec<String> = proof.areas.iter().map(|a| a.to_string()).collect();
+                println!(
+                    "valid, word {} at area {} offset {}",
+                    proof.word,
+                    areas.join("."),
+                    proof.offset
+                );
+            }
+            _ => {
+                println!("invalid");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.len() == 3 && args[1] == "inspect" {
+        print!("{}", inspect::inspect(&load(&args[2], &codes)));
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "diff" {
+        let differences = diff::diff(&load(&args[2], &codes), &load(&args[3], &codes));
+        for difference in differences.iter() {
+            println!("{}", difference);
+        }
+        if !differences.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if (args.len() == 3 || args.len() == 4) && args[1] == "debug" {
+        let recording = if args.len() == 4 {
+            match record::Recording::from_words(&read_words(&args[3])) {
+                Some(recording) => recording,
+                None => {
+                    println!("{}: not a recording", args[3]);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            record::Recording::new()
+        };
+        debug(load(&args[2], &codes), recording);
+        return;
+    }
 
     write_hello_bin();
 
-    let data: Vec<u8> = fs::read("hello.bin").expect("Unable to read file");
-    println!("Read: {} bytes", data.len());
-    let mut flat: Vec<u64> = vec![0;data.len()/8];
-    BigEndian::read_u64_into(&data, &mut flat);
+    let flat = read_words("hello.bin");
+    println!("Read: {} bytes", flat.len() * 8);
 
     //println!("Start");
 
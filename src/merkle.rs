@@ -0,0 +1,442 @@
+use area::*;
+use byteorder::{BigEndian, ByteOrder};
+use formats::*;
+use sha2::{Digest, Sha256};
+use store::Hash;
+
+// Prefixed to what is hashed so words, inner nodes, empty leaves, roots of trees and the two
+// kinds of areas never collide
+const WORD: u8 = 0;
+const NODE: u8 = 1;
+const EMPTY: u8 = 2;
+const ROOT: u8 = 3;
+const DATA: u8 = 4;
+const NESTED: u8 = 5;
+
+fn sha256(prefix: u8, words: &[u64]) -> Hash {
+    let mut bytes: Vec<u8> = vec![0; 1 + words.len() * 8];
+    bytes[0] = prefix;
+    BigEndian::write_u64_into(words, &mut bytes[1..]);
+    let mut hash: Hash = [0; 4];
+    BigEndian::read_u64_into(&Sha256::digest(&bytes), &mut hash);
+    hash
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+    let mut words: Vec<u64> = left.to_vec();
+    words.extend(right);
+    sha256(NODE, &words)
+}
+
+/**
+Hash a root together with the number of leaves below it, which fixes the depth of the tree
+*/
+fn seal(prefix: u8, count: u64, hash: &Hash) -> Hash {
+    let mut words: Vec<u64> = vec![count];
+    words.extend(hash);
+    sha256(prefix, &words)
+}
+
+/**
+Number of siblings on the way up in a tree with count leaves
+*/
+fn depth(count: u64) -> Option<usize> {
+    Some(count.checked_next_power_of_two()?.trailing_zeros() as usize)
+}
+
+/**
+Pad to a power of two with empty leaves and pair nodes level by level. Returns the root, sealed
+with the number of leaves, and the siblings on the way up from index, whose side follows from
+the bits of index.
+*/
+fn tree(mut level: Vec<Hash>, index: usize) -> (Hash, Vec<Hash>) {
+    let count = level.len() as u64;
+    level.resize(level.len().next_power_of_two(), sha256(EMPTY, &[]));
+    let mut siblings: Vec<Hash> = Vec::new();
+    let mut index = index;
+    while level.len() > 1 {
+        siblings.push(level[(index ^ 1).min(level.len() - 1)]);
+        level = level.chunks(2).map(|pair| node(&pair[0], &pair[1])).collect();
+        index /= 2;
+    }
+    (seal(ROOT, count, &level[0]), siblings)
+}
+
+/**
+Root of a tree with count leaves from the leaf at index, only if there are as many siblings as
+such a tree is deep
+*/
+fn climb(mut hash: Hash, index: u64, count: u64, siblings: &[Hash]) -> Option<Hash> {
+    if index >= count || depth(count)? != siblings.len() {
+        return None;
+    }
+    for (level, sibling) in siblings.iter().enumerate() {
+        hash = if (index >> level) & 1 == 1 {
+            node(sibling, &hash)
+        } else {
+            node(&hash, sibling)
+        };
+    }
+    Some(seal(ROOT, count, &hash))
+}
+
+fn words(words: &[u64]) -> Vec<Hash> {
+    words.iter().map(|word| sha256(WORD, &[*word])).collect()
+}
+
+/**
+The same tree as tree builds over the words of an area, built from the allocated pages alone.
+Subtrees of words never written and of padding are the same at every height, so they are
+hashed once per height.
+*/
+struct Sparse<'a> {
+    area: &'a Area,
+    // Roots of subtrees of zero words and of empty leaves by height
+    zeros: Vec<Hash>,
+    empties: Vec<Hash>,
+}
+
+impl<'a> Sparse<'a> {
+    fn new(area: &'a Area) -> Sparse<'a> {
+        let mut zeros = vec![sha256(WORD, &[0])];
+        let mut empties = vec![sha256(EMPTY, &[])];
+        for height in 1..64 {
+            zeros.push(node(&zeros[height - 1], &zeros[height - 1]));
+            empties.push(node(&empties[height - 1], &empties[height - 1]));
+        }
+        Sparse { area, zeros, empties }
+    }
+
+    fn depth(&self) -> usize {
+        depth(self.area.len()).unwrap_or(0)
+    }
+
+    // Root of the subtree of the given height over the leaves from start
+    fn subtree(&self, height: usize, start: u64) -> Hash {
+        let end = start.saturating_add(1 << height);
+        if start >= self.area.len() {
+            self.empties[height]
+        } else if end <= self.area.len() && !self.area.touched(start, end) {
+            self.zeros[height]
+        } else if height == 0 {
+            sha256(WORD, &[self.area.get(start)])
+        } else {
+            let half = 1 << (height - 1);
+            node(&self.subtree(height - 1, start), &self.subtree(height - 1, start + half))
+        }
+    }
+
+    fn root(&self) -> Hash {
+        seal(ROOT, self.area.len(), &self.subtree(self.depth(), 0))
+    }
+
+    // Siblings on the way up from the word at offset
+    fn siblings(&self, offset: u64) -> Vec<Hash> {
+        let mut siblings: Vec<Hash> = Vec::new();
+        let mut start = 0;
+        for height in (1..=self.depth()).rev() {
+            let half = 1 << (height - 1);
+            if offset < start + half {
+                siblings.push(self.subtree(height - 1, start + half));
+            } else {
+                siblings.push(self.subtree(height - 1, start));
+                start += half;
+            }
+        }
+        siblings.reverse();
+        siblings
+    }
+}
+
+// Areas are committed to with their kind and length, those holding a nested process through
+// the root of that process
+fn area(area: &Area) -> Hash {
+    match try_d_area(area) {
+        Some(child) => seal(NESTED, area.len(), &root(&child)),
+        None => seal(DATA, area.len(), &Sparse::new(area).root()),
+    }
+}
+
+/**
+The roots committed to, in this order: header words with the opset, code, stack, map, the
+areas, then calls, fault, shares, outbox and inbox
+*/
+fn parts(sharp: &Process, areas: Vec<Hash>) -> Vec<Hash> {
+    let header = [
+        sharp.header.status,
+        sharp.header.rec,
+        sharp.header.gas,
+        sharp.header.mem,
+        sharp.header.ip,
+        sharp.opset,
+    ];
+    let fault: Vec<u64> = match sharp.fault {
+        Some((area, offset)) => vec![area, offset],
+        None => Vec::new(),
+    };
+    let shares: Vec<u64> = sharp
+        .shares
+        .iter()
+        .flat_map(|share| vec![share.area, share.parent, share.readonly as u64])
+        .collect();
+    vec![
+        tree(words(&header), 0).0,
+        tree(words(&sharp.code), 0).0,
+        tree(words(&sharp.stack), 0).0,
+        tree(words(&sharp.map), 0).0,
+        tree(areas, 0).0,
+        tree(words(&sharp.calls), 0).0,
+        tree(words(&fault), 0).0,
+        tree(words(&shares), 0).0,
+        tree(words(&sharp.outbox), 0).0,
+        tree(words(&sharp.inbox), 0).0,
+    ]
+}
+
+// Position of the areas among the parts, and their number
+const AREAS: usize = 4;
+const PARTS: u64 = 10;
+
+/**
+Merkle root over the whole state of a process, including processes nested in its areas
+*/
+pub fn root(sharp: &Process) -> Hash {
+    let areas = sharp.memory.iter().map(area).collect();
+    tree(parts(sharp, areas), 0).0
+}
+
+/**
+Evidence that a word is stored at an offset of an area, checked against a root alone
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proof {
+    pub areas: Vec<u64>,
+    // Number of areas of the process each area on the path belongs to
+    pub counts: Vec<u64>,
+    // Length of each area on the path
+    pub lengths: Vec<u64>,
+    pub offset: u64,
+    pub word: u64,
+    // Siblings of the area tree, then of the area and part trees of each process up to the root
+    pub siblings: Vec<Vec<Hash>>,
+}
+
+impl Proof {
+    /**
+    The root this proof leads to, None if any tree on the way has a different depth than the
+    number of leaves it commits to
+    */
+    pub fn root(&self) -> Option<Hash> {
+        let levels = self.areas.len();
+        if levels == 0
+            || self.counts.len() != levels
+            || self.lengths.len() != levels
+            || self.siblings.len() != 1 + 2 * levels
+        {
+            return None;
+        }
+        let length = self.lengths[levels - 1];
+        let data = climb(sha256(WORD, &[self.word]), self.offset, length, &self.siblings[0])?;
+        let mut hash = seal(DATA, length, &data);
+        for level in (0..levels).rev() {
+            let siblings = &self.siblings[1 + 2 * (levels - 1 - level)..];
+            hash = climb(hash, self.areas[level], self.counts[level], &siblings[0])?;
+            hash = climb(hash, AREAS as u64, PARTS, &siblings[1])?;
+            if level > 0 {
+                hash = seal(NESTED, self.lengths[level - 1], &hash);
+            }
+        }
+        Some(hash)
+    }
+
+    pub fn verify(&self, root: &Hash) -> bool {
+        self.root() == Some(*root)
+    }
+
+    /**
+    Flatten as the word, the offset, the number of areas, then the index, area count and length
+    of each area, then for each tree the number of siblings and their hash words
+    */
+    pub fn to_words(&self) -> Vec<u64> {
+        let mut flat: Vec<u64> = vec![self.word, self.offset, self.areas.len() as u64];
+        for level in 0..self.areas.len() {
+            flat.extend(&[self.areas[level], self.counts[level], self.lengths[level]]);
+        }
+        for siblings in self.siblings.iter() {
+            flat.push(siblings.len() as u64);
+            for sibling in siblings.iter() {
+                flat.extend(sibling);
+            }
+        }
+        flat
+    }
+
+    pub fn from_words(flat: &[u64]) -> Option<Proof> {
+        let word = *flat.first()?;
+        let offset = *flat.get(1)?;
+        let count = *flat.get(2)? as usize;
+        let path = flat.get(3..count.checked_mul(3)?.checked_add(3)?)?;
+        let mut pos = 3 + path.len();
+        let mut siblings: Vec<Vec<Hash>> = Vec::new();
+        while pos < flat.len() {
+            let len = flat[pos] as usize;
+            let words = flat.get(pos + 1..(pos + 1).checked_add(len.checked_mul(4)?)?)?;
+            siblings.push(words.chunks(4).map(|h| [h[0], h[1], h[2], h[3]]).collect());
+            pos += 1 + len * 4;
+        }
+        Some(Proof {
+            areas: path.chunks(3).map(|level| level[0]).collect(),
+            counts: path.chunks(3).map(|level| level[1]).collect(),
+            lengths: path.chunks(3).map(|level| level[2]).collect(),
+            offset,
+            word,
+            siblings,
+        })
+    }
+}
+
+/**
+Prove the word at offset of the area reached by following areas, each but the last
+holding a nested process
+*/
+pub fn prove(sharp: &Process, areas: &[u64], offset: u64) -> Option<Proof> {
+    let (index, rest) = areas.split_first()?;
+    let held = sharp.memory.get(*index as usize)?;
+    let mut proof = if rest.is_empty() {
+        if offset >= held.len() || try_d_area(held).is_some() {
+            return None;
+        }
+        Proof {
+            areas: Vec::new(),
+            counts: Vec::new(),
+            lengths: Vec::new(),
+            offset,
+            word: held.get(offset),
+            siblings: vec![Sparse::new(held).siblings(offset)],
+        }
+    } else {
+        prove(&try_d_area(held)?, rest, offset)?
+    };
+
+    let roots: Vec<Hash> = sharp.memory.iter().map(area).collect();
+    proof.areas.insert(0, *index);
+    proof.counts.insert(0, roots.len() as u64);
+    proof.lengths.insert(0, held.len());
+    proof.siblings.push(tree(roots.clone(), *index as usize).1);
+    proof.siblings.push(tree(parts(sharp, roots), AREAS).1);
+    Some(proof)
+}
+
+pub fn hex(hash: &Hash) -> String {
+    hash.iter().map(|word| format!("{:016x}", word)).collect()
+}
+
+pub fn from_hex(text: &str) -> Option<Hash> {
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut hash: Hash = [0; 4];
+    for (i, word) in hash.iter_mut().enumerate() {
+        *word = u64::from_str_radix(&text[i * 16..i * 16 + 16], 16).ok()?;
+    }
+    Some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A root whose area 0 holds a child with a data area of three words and a plain area
+    fn nested() -> Process {
+        let mut child = d(&[0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0]);
+        child.memory.push(Area::from(vec![4, 0, 9]));
+        let mut sharp = d(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        sharp.memory.push(Area::from(s(&child)));
+        sharp.memory.push(Area::from(vec![1, 2, 3, 4, 5]));
+        sharp
+    }
+
+    #[test]
+    fn proofs_of_nested_words_verify() {
+        let sharp = nested();
+        let root = root(&sharp);
+        let proof = prove(&sharp, &[0, 0], 2).unwrap();
+        assert_eq!(proof.word, 9);
+        assert!(proof.verify(&root));
+        assert_eq!(Proof::from_words(&proof.to_words()), Some(proof.clone()));
+
+        let mut wrong = proof.clone();
+        wrong.word = 8;
+        assert!(!wrong.verify(&root));
+
+        let plain = prove(&sharp, &[1], 4).unwrap();
+        assert_eq!(plain.word, 5);
+        assert!(plain.verify(&root));
+
+        assert!(prove(&sharp, &[0], 2).is_none());
+        assert!(prove(&sharp, &[1], 5).is_none());
+    }
+
+    #[test]
+    fn joined_sibling_lists_do_not_verify() {
+        let sharp = nested();
+        let root = root(&sharp);
+        let proof = prove(&sharp, &[0, 0], 2).unwrap();
+        assert_eq!(sharp.memory[0].len(), 15);
+
+        // Claim the child's word is a word of the child snapshot area itself, climbing through
+        // the child's trees as if they were part of one taller area tree
+        let joined: Vec<Hash> = proof.siblings[..3].iter().flatten().cloned().collect();
+        let forged = Proof {
+            areas: vec![0],
+            counts: vec![proof.counts[0]],
+            lengths: vec![15],
+            offset: 18,
+            word: 9,
+            siblings: vec![joined, proof.siblings[3].clone(), proof.siblings[4].clone()],
+        };
+        assert_eq!(forged.root(), None);
+        assert!(!forged.verify(&root));
+
+        // Any length that would make the depth fit moves the offset out of the area
+        for length in 0..64 {
+            let forged = Proof {
+                lengths: vec![length],
+                ..forged.clone()
+            };
+            assert!(!forged.verify(&root));
+        }
+    }
+
+    #[test]
+    fn sparse_areas_hash_like_their_words() {
+        for len in [0, 1, 2, 3, 5, 8, 700, 703, 704, 1023, 1024, 1025, 2048, 2049] {
+            let mut sparse = Area::default();
+            sparse.resize(len);
+            let mut flat = vec![0; len as usize];
+            for offset in (0..len).filter(|offset| offset % 700 == 3 || offset % 1024 == 1023) {
+                sparse.set(offset, offset);
+                flat[offset as usize] = offset;
+            }
+            assert_eq!(Sparse::new(&sparse).root(), tree(words(&flat), 0).0);
+            for offset in (0..len).step_by(61) {
+                let siblings = tree(words(&flat), offset as usize).1;
+                assert_eq!(Sparse::new(&sparse).siblings(offset), siblings);
+            }
+        }
+    }
+
+    #[test]
+    fn huge_sparse_areas_cost_what_they_store() {
+        let mut huge = Area::default();
+        huge.resize(1 << 40);
+        huge.set(1 << 39, 7);
+        let mut sharp = d(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        sharp.memory.push(huge);
+        let root = root(&sharp);
+        let proof = prove(&sharp, &[0], 1 << 39).unwrap();
+        assert_eq!(proof.word, 7);
+        assert!(proof.verify(&root));
+        assert!(prove(&sharp, &[0], 1 << 40).is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use ops::*;
+use formats::*;
+
+/**
+Hooks called by the vm while it runs, depth is the nesting level of the process involved with 0
+for the one passed to run. Every callback does nothing by default, so implementations only
+override what they need.
+*/
+pub trait Observer {
+    /**
+    An instruction passed the resource checks and is about to execute
+    */
+    fn before(&mut self, _state: &Process, _depth: usize, _instr: IS) {}
+
+    /**
+    An instruction executed, state already reflects its effects
+    */
+    fn after(&mut self, _state: &Process, _depth: usize, _instr: IS) {}
+
+    /**
+    A process left the normal status
+    */
+    fn status(&mut self, _state: &Process, _depth: usize, _status: u64) {}
+
+    /**
+    RUN started or resumed a child
+    */
+    fn enter(&mut self, _child: &Process, _depth: usize) {}
+
+    /**
+    A child stopped and is about to be serialized back into its parent
+    */
+    fn exit(&mut self, _child: &Process, _depth: usize) {}
+
+    /**
+    A process yielded to its parent or host
+    */
+    fn yielded(&mut self, _state: &Process, _depth: usize) {}
+
+    /**
+    An area was created or resized to len words
+    */
+    fn alloc(&mut self, _state: &Process, _depth: usize, _area: u64, _len: u64) {}
+
+    /**
+    A process stopped on an invalid memory access
+    */
+    fn fault(&mut self, _state: &Process, _depth: usize, _area: u64, _offset: u64) {}
+
+    /**
+    Asked before every instruction when running with resume, returning true stops right there
+    and leaves the nest to be resumed later
+    */
+    fn pause(&mut self) -> bool {
+        false
+    }
+}
+
+/**
+Observer that ignores everything, compiling down to no work at all
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silent;
+
+impl Observer for Silent {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use area::*;
+    use vm::*;
+
+    // Every callback as text, with the depth and what identifies the event
+    #[derive(Default)]
+    struct Log {
+        events: Vec<String>,
+    }
+
+    impl Observer for Log {
+        fn before(&mut self, state: &Process, depth: usize, instr: IS) {
+            let ip = state.header.ip;
+            self.events.push(format!("before {} {:?}@{}", depth, instr, ip));
+        }
+
+        fn after(&mut self, state: &Process, depth: usize, instr: IS) {
+            let ip = state.header.ip;
+            self.events.push(format!("after {} {:?}@{}", depth, instr, ip));
+        }
+
+        fn status(&mut self, _state: &Process, depth: usize, status: u64) {
+            self.events.push(format!("status {} {}", depth, status));
+        }
+
+        fn enter(&mut self, child: &Process, depth: usize) {
+            self.events.push(format!("enter {} {:?}", depth, child.code));
+        }
+
+        fn exit(&mut self, child: &Process, depth: usize) {
+            self.events.push(format!("exit {} {:?}", depth, child.stack));
+        }
+    }
+
+    #[test]
+    fn callbacks_follow_execution_in_order() {
+        // PUSH 5, HALT
+        let mut child = d(&[0, 0, 0, 0, 0, 3, 0, 0, 0, 6, 5, 0]);
+        child.opset = OPSET;
+        // PUSH 0, PUSH gas, PUSH mem, RUN, HALT
+        let mut sharp = d(&[0, 0, 0, 0, 0, 8, 0, 0, 0, 6, 0, 6, 100, 6, 10_000, 3, 0]);
+        sharp.opset = OPSET;
+        sharp.memory.push(Area::from(s(&child)));
+
+        let mut log = Log::default();
+        observe(sharp, 1_000, 1_000_000, MAX_DEPTH, false, &mut log);
+        assert_eq!(
+            log.events,
+            vec![
+                "before 0 PUSH@0",
+                "after 0 PUSH@2",
+                "before 0 PUSH@2",
+                "after 0 PUSH@4",
+                "before 0 PUSH@4",
+                "after 0 PUSH@6",
+                // RUN starts the child and is executed again once the child stopped
+                "before 0 RUN@6",
+                "enter 1 [6, 5, 0]",
+                "after 0 RUN@6",
+                "before 1 PUSH@0",
+                "after 1 PUSH@2",
+                "before 1 HALT@2",
+                "after 1 HALT@3",
+                "status 1 1",
+                "exit 1 [5]",
+                "before 0 RUN@6",
+                "after 0 RUN@7",
+                "before 0 HALT@7",
+                "after 0 HALT@8",
+                "status 0 1",
+            ]
+        );
+    }
+}
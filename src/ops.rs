@@ -1,7 +1,10 @@
 extern crate num_derive;
 pub extern crate num_traits;
 
-#[derive(Debug, PartialEq, num_derive::FromPrimitive)]
+use ops::num_traits::FromPrimitive;
+
+#[derive(Debug, Clone, Copy, PartialEq, num_derive::FromPrimitive)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum IS {
     HALT,
     RETURN,
@@ -36,6 +39,68 @@ pub enum IS {
     ECVERIFY,
     ROT,
     ROT2,
+    CALL,
+    RET,
+    AND,
+    OR,
+    XOR,
+    SHL,
+    SHR,
+    SAR,
+    LT,
+    GT,
+    EQ,
+    SLT,
+    SGT,
+    MEMCOPY,
+    MEMFILL,
+    MEMCMP,
+    SHARE,
+    INSPECT,
+    PEEK,
+    KILL,
+    RESET,
+    SEND,
+    SENDAREA,
+    REPLY,
+    RECV,
+}
+
+// Additional gas per word touched by bulk memory instructions
+pub const WORD_GAS: u64 = 1;
+
+// Latest opcode set, snapshots without an opcode set section use set 0
+pub const OPSET: u64 = 5;
+
+/**
+Opcode set an instruction was introduced in
+*/
+pub fn opset(is: IS) -> u64 {
+    match is {
+        IS::CALL | IS::RET => 1,
+        IS::AND | IS::OR | IS::XOR | IS::SHL | IS::SHR | IS::SAR => 1,
+        IS::LT | IS::GT | IS::EQ | IS::SLT | IS::SGT => 1,
+        IS::MEMCOPY | IS::MEMFILL | IS::MEMCMP => 2,
+        IS::SHARE => 3,
+        IS::INSPECT | IS::PEEK | IS::KILL | IS::RESET => 4,
+        IS::SEND | IS::SENDAREA | IS::REPLY | IS::RECV => 5,
+        _ => 0,
+    }
+}
+
+/**
+Decode an instruction word, rejecting opcodes newer than the snapshot's opcode set
+*/
+pub fn lookup(instr: u64, set: u64) -> Option<IS> {
+    IS::from_u64(instr).filter(|i| opset(*i) <= set)
+}
+
+/**
+Whether RUN hands back what a child sent under an opcode set, which it does from the set that
+introduced SEND on
+*/
+pub fn delivers(set: u64) -> bool {
+    set >= opset(IS::SEND)
 }
 
 #[derive(Clone)]
@@ -46,6 +111,18 @@ pub struct Requirement {
     pub gas_cost: i32,
 }
 
+/**
+Requirement of an instruction under an opcode set, RUN only pops its arguments before the set
+that lets children send
+*/
+pub fn requirement_in(is: IS, set: u64) -> Requirement {
+    let mut reqs = requirement(is);
+    if is == IS::RUN && !delivers(set) {
+        reqs.addtl_mem = -3;
+    }
+    reqs
+}
+
 //Instruction length, stack reqs, additional memory, gas cost
 pub fn requirement(is: IS) -> Requirement {
     match is {
@@ -67,10 +144,12 @@ pub fn requirement(is: IS) -> Requirement {
             addtl_mem: 0,
             gas_cost: 1,
         },
+        // Pops area, gas and mem, then pushes the words the child sent and their count, see
+        // requirement_in for the sets before
         IS::RUN => Requirement {
             length: 1,
             stack_req: 3,
-            addtl_mem: -3,
+            addtl_mem: -2,
             gas_cost: 0,
         },
         IS::JUMP => Requirement {
@@ -247,5 +326,155 @@ pub fn requirement(is: IS) -> Requirement {
             addtl_mem: 0,
             gas_cost: 10,
         },
+        IS::CALL => Requirement {
+            length: 1,
+            stack_req: 1,
+            addtl_mem: -1,
+            gas_cost: 4,
+        }, //call/ret
+        IS::RET => Requirement {
+            length: 1,
+            stack_req: 0,
+            addtl_mem: 0,
+            gas_cost: 4,
+        },
+        IS::AND => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 4,
+        }, //bitwise
+        IS::OR => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 4,
+        },
+        IS::XOR => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 4,
+        },
+        IS::SHL => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        },
+        IS::SHR => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        },
+        IS::SAR => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        },
+        IS::LT => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        }, //comparison
+        IS::GT => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        },
+        IS::EQ => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        },
+        IS::SLT => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        },
+        IS::SGT => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 6,
+        },
+        IS::MEMCOPY => Requirement {
+            length: 1,
+            stack_req: 5,
+            addtl_mem: -5,
+            gas_cost: 10,
+        }, //bulk, plus WORD_GAS per word
+        IS::MEMFILL => Requirement {
+            length: 1,
+            stack_req: 4,
+            addtl_mem: -4,
+            gas_cost: 10,
+        },
+        IS::MEMCMP => Requirement {
+            length: 1,
+            stack_req: 5,
+            addtl_mem: -4,
+            gas_cost: 10,
+        },
+        IS::SHARE => Requirement {
+            length: 1,
+            stack_req: 3,
+            addtl_mem: -2,
+            gas_cost: 10,
+        }, //child areas
+        IS::INSPECT => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -1,
+            gas_cost: 4,
+        }, //child control
+        IS::PEEK => Requirement {
+            length: 1,
+            stack_req: 1,
+            addtl_mem: 0,
+            gas_cost: 4,
+        },
+        IS::KILL => Requirement {
+            length: 1,
+            stack_req: 1,
+            addtl_mem: -1,
+            gas_cost: 10,
+        },
+        IS::RESET => Requirement {
+            length: 1,
+            stack_req: 1,
+            addtl_mem: -1,
+            gas_cost: 10,
+        },
+        IS::SEND => Requirement {
+            length: 1,
+            stack_req: 1,
+            addtl_mem: -1,
+            gas_cost: 4,
+        }, //messages
+        IS::SENDAREA => Requirement {
+            length: 1,
+            stack_req: 3,
+            addtl_mem: -3,
+            gas_cost: 4,
+        },
+        IS::REPLY => Requirement {
+            length: 1,
+            stack_req: 2,
+            addtl_mem: -2,
+            gas_cost: 10,
+        },
+        IS::RECV => Requirement {
+            length: 1,
+            stack_req: 0,
+            addtl_mem: 2,
+            gas_cost: 4,
+        },
     }
 }
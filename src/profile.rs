@@ -0,0 +1,223 @@
+use ops::*;
+use ops::num_traits::FromPrimitive;
+use formats::*;
+use observer::*;
+use disasm::*;
+use store::*;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Addresses listed with their disassembly in the report
+const HOT: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Hits {
+    pub count: u64,
+    pub gas: u64,
+    pub mem: u64,
+}
+
+impl Hits {
+    fn add(&mut self, gas: u64, mem: u64) {
+        self.count += 1;
+        self.gas += gas;
+        self.mem += mem;
+    }
+}
+
+/**
+Observer counting executions and charged gas and mem per opcode and per code address, with
+addresses told apart by the nesting depth they ran at and the hash of the code they belong to.
+Gas and mem are what the executing process itself was charged, children are accounted
+separately.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub opcodes: BTreeMap<u64, Hits>,
+    pub addresses: BTreeMap<(usize, Hash, u64), Hits>,
+    pub stacks: BTreeMap<String, u64>,
+    listing: BTreeMap<(Hash, u64), String>,
+    codes: Vec<Hash>,
+    runs: Vec<u64>,
+    last: (Hash, u64, u64, u64),
+    stack: String,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /**
+    Frames leading to the instruction at ip: RUN sites of the ancestors, then CALL sites
+    */
+    fn frames(&self, state: &Process, code: Hash, ip: u64, depth: usize) -> String {
+        let mut frames: Vec<String> = self.runs[..depth]
+            .iter()
+            .map(|ip| format!("run@{}", ip))
+            .collect();
+        for ret in state.calls.iter() {
+            frames.push(format!("call@{}", ret.saturating_sub(1)));
+        }
+        frames.push(format!("{} {}", ip, self.listing[&(code, ip)]));
+        frames.join(";")
+    }
+
+    /**
+    Opcodes and addresses sorted by mem, with the hottest addresses disassembled
+    */
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        let mut opcodes: Vec<(&u64, &Hits)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.mem.cmp(&a.1.mem).then(b.1.count.cmp(&a.1.count)));
+        writeln!(out, "{:>12} {:>12} {:>12}  opcode", "mem", "gas", "count").unwrap();
+        for (instr, hits) in opcodes {
+            let name = match IS::from_u64(*instr) {
+                Some(i) => format!("{:?}", i),
+                None => format!("?{}", instr),
+            };
+            writeln!(out, "{:>12} {:>12} {:>12}  {}", hits.mem, hits.gas, hits.count, name)
+                .unwrap();
+        }
+
+        let mut addresses: Vec<(&(usize, Hash, u64), &Hits)> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.mem.cmp(&a.1.mem).then(a.0.cmp(b.0)));
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "{:>12} {:>12} {:>12} {:>5} {:>16} {:>8}  instruction",
+            "mem", "gas", "count", "depth", "code", "ip"
+        )
+        .unwrap();
+        for (i, (&(depth, code, ip), hits)) in addresses.iter().enumerate() {
+            let listing = if i < HOT { &self.listing[&(code, ip)][..] } else { "" };
+            writeln!(
+                out,
+                "{:>12} {:>12} {:>12} {:>5} {:016x} {:>8}  {}",
+                hits.mem, hits.gas, hits.count, depth, code[0], ip, listing
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /**
+    One line per distinct stack with the mem spent at its top, as read by flamegraph tools
+    */
+    pub fn collapsed(&self) -> String {
+        let mut out = String::new();
+        for (stack, mem) in self.stacks.iter() {
+            writeln!(out, "{} {}", stack, mem).unwrap();
+        }
+        out
+    }
+}
+
+impl Observer for Profile {
+    fn before(&mut self, state: &Process, depth: usize, _instr: IS) {
+        let ip = state.header.ip;
+        if self.codes.len() <= depth {
+            self.codes.push(hash(&state.code));
+        }
+        let code = self.codes[depth];
+        self.last = (code, ip, state.header.mem, state.header.gas);
+        self.listing
+            .entry((code, ip))
+            .or_insert_with(|| instruction(&state.code, ip as usize, state.opset));
+        self.stack = self.frames(state, code, ip, depth);
+    }
+
+    fn after(&mut self, state: &Process, depth: usize, instr: IS) {
+        let (code, ip, mem, gas) = self.last;
+        let spent = mem.saturating_sub(state.header.mem);
+        let burnt = gas.saturating_sub(state.header.gas);
+
+        self.opcodes.entry(instr as u64).or_default().add(burnt, spent);
+        self.addresses.entry((depth, code, ip)).or_default().add(burnt, spent);
+        *self.stacks.entry(self.stack.clone()).or_insert(0) += spent;
+    }
+
+    fn enter(&mut self, child: &Process, depth: usize) {
+        self.runs.truncate(depth - 1);
+        self.runs.push(self.last.1);
+        self.codes.truncate(depth);
+        self.codes.push(hash(&child.code));
+    }
+
+    fn exit(&mut self, _child: &Process, depth: usize) {
+        self.runs.truncate(depth - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::*;
+
+    fn process(code: &[u64], areas: &[Vec<u64>]) -> Process {
+        let mut flat = vec![0, 0, 0, 0, 0, code.len() as u64, 0, 0, areas.len() as u64];
+        flat.extend(code);
+        for area in areas {
+            flat.push(area.len() as u64);
+            flat.extend(area);
+        }
+        let mut sharp = d(&flat);
+        sharp.opset = OPSET;
+        sharp
+    }
+
+    #[test]
+    fn children_with_different_code_are_profiled_apart() {
+        // Two children whose first instruction differs, both run at depth 1
+        let first = s(&process(&[6, 1, 0], &[]));
+        let second = s(&process(&[19, 0], &[]));
+        let mut code = vec![6, 0, 6, 100, 6, 100_000, 3, 6, 1, 6, 100, 6, 100_000, 3];
+        code.push(0);
+        let mut profile = Profile::new();
+        observe(process(&code, &[first, second]), 1_000, 1_000_000, MAX_DEPTH, false, &mut profile);
+
+        let first = (hash(&[6, 1, 0]), 0);
+        let second = (hash(&[19, 0]), 0);
+        assert!(profile.listing[&first].contains("PUSH"));
+        assert!(profile.listing[&second].contains("AREA"));
+    }
+
+    #[test]
+    fn charged_mem_grows_with_the_process_size() {
+        let mut profile = Profile::new();
+        // PUSH 1, HALT
+        let sharp = process(&[6, 1, 0], &[vec![0; 100]]);
+        observe(sharp, 1_000, 1_000_000, MAX_DEPTH, false, &mut profile);
+        let push = profile.opcodes[&(IS::PUSH as u64)];
+        assert_eq!(push.count, 1);
+        assert!(push.mem > 100);
+    }
+
+    #[test]
+    fn the_same_code_is_profiled_apart_at_each_depth() {
+        // PUSH 0, PUSH gas, PUSH mem, RUN, HALT at depth 0 and 1, PUSH 1, HALT at depth 2
+        let code = [6, 0, 6, 10_000, 6, 1_000_000, 3, 0];
+        let grandchild = s(&process(&[6, 1, 0], &[]));
+        let child = s(&process(&code, &[grandchild]));
+        let mut profile = Profile::new();
+        let sharp = process(&code, &[child]);
+        observe(sharp, 100_000, 100_000_000, MAX_DEPTH, false, &mut profile);
+
+        let run = hash(&code);
+        for depth in 0..2 {
+            let hits = profile.addresses[&(depth, run, 0)];
+            assert_eq!(hits.count, 1);
+            assert_eq!(hits.gas, 1);
+            assert!(hits.mem > 0);
+        }
+        assert_eq!(profile.addresses[&(2, hash(&[6, 1, 0]), 0)].count, 1);
+        assert_eq!(profile.opcodes[&(IS::PUSH as u64)].count, 7);
+        assert_eq!(profile.opcodes[&(IS::PUSH as u64)].gas, 7);
+
+        let report = profile.report();
+        assert!(report.contains("depth"));
+        assert!(report.lines().any(|line| line.split_whitespace().nth(3) == Some("2")));
+    }
+}
@@ -0,0 +1,111 @@
+/**
+A host function call as seen from the guest: the function id and stack it yielded with,
+and the serialized process as the host function left it
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostCall {
+    pub funid: u64,
+    pub stack: Vec<u64>,
+    pub effect: Vec<u64>,
+}
+
+/**
+Every host call of a run in order, enough to repeat the run without the host
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub calls: Vec<HostCall>,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording::default()
+    }
+
+    /**
+    Flatten to words: the number of calls, then per call its function id,
+    stack length and stack, effect length and effect
+    */
+    pub fn to_words(&self) -> Vec<u64> {
+        let mut flat: Vec<u64> = vec![self.calls.len() as u64];
+        for call in self.calls.iter() {
+            flat.push(call.funid);
+            flat.push(call.stack.len() as u64);
+            flat.extend(&call.stack);
+            flat.push(call.effect.len() as u64);
+            flat.extend(&call.effect);
+        }
+        flat
+    }
+
+    /**
+    Read back what to_words wrote, None if the words are cut short or run on past the last call
+    */
+    pub fn from_words(flat: &[u64]) -> Option<Recording> {
+        let mut recording = Recording::new();
+        let mut start: usize = 1;
+        for _call in 0..*flat.first()? {
+            let funid = *flat.get(start)?;
+            let stacklen = *flat.get(start + 1)? as usize;
+            start += 2;
+            let end = start.checked_add(stacklen)?;
+            let stack = flat.get(start..end)?.to_vec();
+            let effectlen = *flat.get(end)? as usize;
+            start = end + 1;
+            let end = start.checked_add(effectlen)?;
+            let effect = flat.get(start..end)?.to_vec();
+            start = end;
+            recording.calls.push(HostCall {
+                funid,
+                stack,
+                effect,
+            });
+        }
+        if start != flat.len() {
+            return None;
+        }
+        Some(recording)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording() -> Recording {
+        Recording {
+            calls: vec![
+                HostCall {
+                    funid: 7,
+                    stack: vec![7, 5],
+                    effect: vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 10],
+                },
+                HostCall {
+                    funid: 8,
+                    stack: vec![],
+                    effect: vec![],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn recordings_read_back_what_they_wrote() {
+        let flat = recording().to_words();
+        assert_eq!(Recording::from_words(&flat), Some(recording()));
+        assert_eq!(Recording::from_words(&[0]), Some(Recording::new()));
+    }
+
+    #[test]
+    fn malformed_recordings_are_not_read() {
+        let flat = recording().to_words();
+        assert_eq!(Recording::from_words(&[]), None);
+        for len in 1..flat.len() {
+            assert_eq!(Recording::from_words(&flat[..len]), None);
+        }
+        let mut longer = flat.clone();
+        longer.push(0);
+        assert_eq!(Recording::from_words(&longer), None);
+        assert_eq!(Recording::from_words(&[1, 7, u64::MAX, 0]), None);
+    }
+}
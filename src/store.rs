@@ -0,0 +1,80 @@
+use byteorder::{BigEndian, ByteOrder};
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+
+// SHA-256 of the big-endian bytes of a code section, as four words
+pub type Hash = [u64; 4];
+
+pub fn hash(code: &[u64]) -> Hash {
+    let mut bytes: Vec<u8> = vec![0; code.len() * 8];
+    BigEndian::write_u64_into(code, &mut bytes);
+    let digest = Sha256::digest(&bytes);
+    let mut words: Hash = [0; 4];
+    BigEndian::read_u64_into(&digest, &mut words);
+    words
+}
+
+/**
+Code sections kept once each under their hash, so snapshots can refer to them instead of
+embedding them and caches of decoded code can be shared by hash
+*/
+pub struct CodeStore {
+    codes: HashMap<Hash, Vec<u64>>,
+}
+
+impl CodeStore {
+    pub fn new() -> CodeStore {
+        CodeStore {
+            codes: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, code: &[u64]) -> Hash {
+        let key = hash(code);
+        self.codes.entry(key).or_insert_with(|| code.to_vec());
+        key
+    }
+
+    pub fn get(&self, key: &Hash) -> Option<&Vec<u64>> {
+        self.codes.get(key)
+    }
+
+    pub fn merge(&mut self, other: &CodeStore) {
+        for code in other.codes.values() {
+            self.insert(code);
+        }
+    }
+
+    /**
+    Flatten as the number of sections, then the length and words of each, hashes are not
+    stored since they are recomputed when reading
+    */
+    pub fn to_words(&self) -> Vec<u64> {
+        let mut keys: Vec<&Hash> = self.codes.keys().collect();
+        keys.sort();
+        let mut flat: Vec<u64> = vec![keys.len() as u64];
+        for key in keys {
+            let code = &self.codes[key];
+            flat.push(code.len() as u64);
+            flat.extend(code);
+        }
+        flat
+    }
+
+    pub fn from_words(flat: &[u64]) -> CodeStore {
+        let mut store = CodeStore::new();
+        let count = flat.first().cloned().unwrap_or(0);
+        let mut pos: usize = 1;
+        for _code in 0..count {
+            let len = match flat.get(pos) {
+                Some(len) => *len as usize,
+                None => break,
+            };
+            let end = (pos + 1).saturating_add(len).min(flat.len());
+            store.insert(&flat[pos + 1..end]);
+            pos = end;
+        }
+        store
+    }
+}
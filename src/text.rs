@@ -0,0 +1,441 @@
+use ops::*;
+use ops::num_traits::FromPrimitive;
+use formats::*;
+use area::*;
+use flow::*;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::fmt;
+
+// Snapshots as TOML, read and written with the toml crate. Header fields are keys, the status is
+// given by name, code is a multi-line string with one instruction per line and raw numbers for
+// words that do not decode. Areas list their length and the runs of nonzero words as
+// [offset, words...], areas holding a nested process that serializes back to the same words are
+// written as a process table instead. Words too large for a TOML integer are hex strings.
+
+/**
+A word, as an integer where TOML allows it and as a string of hex digits otherwise
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Word(u64);
+
+impl Serialize for Word {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0 <= i64::MAX as u64 {
+            serializer.serialize_i64(self.0 as i64)
+        } else {
+            serializer.serialize_str(&format!("{:#x}", self.0))
+        }
+    }
+}
+
+struct WordVisitor;
+
+impl<'de> Visitor<'de> for WordVisitor {
+    type Value = Word;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number from 0 to 2^64-1, or one in a string")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Word, E> {
+        if value < 0 {
+            return Err(E::custom(format!("{} is negative", value)));
+        }
+        Ok(Word(value as u64))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Word, E> {
+        Ok(Word(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Word, E> {
+        let parsed = match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse(),
+        };
+        parsed
+            .map(Word)
+            .map_err(|_| E::custom(format!("{} is not a word", value)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Word {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Word, D::Error> {
+        deserializer.deserialize_any(WordVisitor)
+    }
+}
+
+fn words(words: &[u64]) -> Vec<Word> {
+    words.iter().map(|w| Word(*w)).collect()
+}
+
+fn unwords(words: &[Word]) -> Vec<u64> {
+    words.iter().map(|w| w.0).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Status {
+    Name(String),
+    Number(Word),
+}
+
+impl Default for Status {
+    fn default() -> Status {
+        Status::Number(Word(0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Code {
+    Source(String),
+    Words(Vec<Word>),
+}
+
+impl Default for Code {
+    fn default() -> Code {
+        Code::Words(Vec::new())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShareText {
+    #[serde(default)]
+    area: Word,
+    #[serde(default)]
+    parent: Word,
+    #[serde(default)]
+    readonly: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AreaText {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    length: Option<Word>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<Vec<Word>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    process: Option<Box<ProcessText>>,
+}
+
+// Values have to come before tables and arrays of tables in TOML
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProcessText {
+    #[serde(default)]
+    status: Status,
+    #[serde(default)]
+    rec: Word,
+    #[serde(default)]
+    gas: Word,
+    #[serde(default)]
+    mem: Word,
+    #[serde(default)]
+    ip: Word,
+    #[serde(default)]
+    opset: Word,
+    #[serde(default)]
+    code: Code,
+    #[serde(default)]
+    stack: Vec<Word>,
+    #[serde(default)]
+    map: Vec<Word>,
+    #[serde(default)]
+    calls: Vec<Word>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fault: Option<(Word, Word)>,
+    #[serde(default)]
+    outbox: Vec<Word>,
+    #[serde(default)]
+    inbox: Vec<Word>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shares: Vec<ShareText>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    areas: Vec<AreaText>,
+}
+
+fn status_name(status: u64) -> Status {
+    match Stati::from_u64(status) {
+        Some(name) => Status::Name(format!("{:?}", name)),
+        None => Status::Number(Word(status)),
+    }
+}
+
+fn code(sharp: &Process) -> String {
+    let mut out = String::new();
+    let mut ip: usize = 0;
+    while ip < sharp.code.len() {
+        match decode(&sharp.code, ip, sharp.opset) {
+            Some((i, reqs)) => {
+                out.push_str(&format!("{:?}", i));
+                for word in sharp.code[ip + 1..ip + reqs.length as usize].iter() {
+                    out.push_str(&format!(" {}", word));
+                }
+                ip += reqs.length as usize;
+            }
+            None => {
+                out.push_str(&sharp.code[ip].to_string());
+                ip += 1;
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/**
+Runs of nonzero words as offset followed by the words, without reading unwritten pages
+*/
+fn runs(area: &Area) -> Vec<Vec<Word>> {
+    let mut runs: Vec<Vec<Word>> = Vec::new();
+    let mut end: u64 = 0;
+    for (start, page) in area.runs() {
+        for (i, word) in page.iter().enumerate() {
+            let offset = start + i as u64;
+            if *word == 0 {
+                continue;
+            }
+            if runs.is_empty() || end != offset {
+                runs.push(vec![Word(offset)]);
+            }
+            runs.last_mut().unwrap().push(Word(*word));
+            end = offset + 1;
+        }
+    }
+    runs
+}
+
+fn process(sharp: &Process) -> ProcessText {
+    let mut areas: Vec<AreaText> = Vec::new();
+    for area in sharp.memory.iter() {
+        // Only areas mostly written can hold a snapshot, the rest are not materialized
+        let stored: u64 = area.runs().iter().map(|run| run.1.len() as u64).sum();
+        let child = if area.len() <= 2 * stored {
+            let flat = area.to_vec();
+            try_d(&flat).filter(|child| s(child) == flat)
+        } else {
+            None
+        };
+        areas.push(match child {
+            Some(child) => AreaText {
+                process: Some(Box::new(process(&child))),
+                ..AreaText::default()
+            },
+            None => AreaText {
+                length: Some(Word(area.len())),
+                data: Some(runs(area)),
+                process: None,
+            },
+        });
+    }
+
+    ProcessText {
+        status: status_name(sharp.header.status),
+        rec: Word(sharp.header.rec),
+        gas: Word(sharp.header.gas),
+        mem: Word(sharp.header.mem),
+        ip: Word(sharp.header.ip),
+        opset: Word(sharp.opset),
+        code: Code::Source(code(sharp)),
+        stack: words(&sharp.stack),
+        map: words(&sharp.map),
+        calls: words(&sharp.calls),
+        fault: sharp.fault.map(|(area, offset)| (Word(area), Word(offset))),
+        outbox: words(&sharp.outbox),
+        inbox: words(&sharp.inbox),
+        shares: sharp
+            .shares
+            .iter()
+            .map(|share| ShareText {
+                area: Word(share.area),
+                parent: Word(share.parent),
+                readonly: share.readonly,
+            })
+            .collect(),
+        areas,
+    }
+}
+
+/**
+Render a snapshot as TOML that from_text reads back to the same snapshot
+*/
+pub fn to_text(sharp: &Process) -> String {
+    let mut out = String::new();
+    process(sharp)
+        .serialize(::toml::Serializer::new(&mut out).pretty_string(true))
+        .expect("Snapshots always fit into TOML");
+    out
+}
+
+fn mnemonic(name: &str) -> Option<u64> {
+    (0..=u8::MAX as u64)
+        .filter_map(IS::from_u64)
+        .find(|i| format!("{:?}", i) == name)
+        .map(|i| i as u64)
+}
+
+fn assemble(source: &str) -> Result<Vec<u64>, String> {
+    let mut code: Vec<u64> = Vec::new();
+    for line in source.lines() {
+        let line = line.split('#').next().unwrap();
+        for (i, token) in line.split_whitespace().enumerate() {
+            match token.parse::<u64>() {
+                Ok(word) => code.push(word),
+                Err(_) if i == 0 => match mnemonic(token) {
+                    Some(instr) => code.push(instr),
+                    None => return Err(format!("unknown instruction {}", token)),
+                },
+                Err(_) => return Err(format!("{} is not a number", token)),
+            }
+        }
+    }
+    Ok(code)
+}
+
+fn build(text: &ProcessText) -> Result<Process, String> {
+    let status = match text.status {
+        Status::Name(ref name) => (0..=u8::MAX as u64)
+            .find(|s| Stati::from_u64(*s).is_some_and(|st| format!("{:?}", st) == *name))
+            .ok_or(format!("unknown status {}", name))?,
+        Status::Number(word) => word.0,
+    };
+    let code = match text.code {
+        Code::Source(ref source) => assemble(source)?,
+        Code::Words(ref code) => unwords(code),
+    };
+
+    let mut memory: Vec<Area> = Vec::new();
+    for area in text.areas.iter() {
+        match area.process {
+            Some(ref child) => {
+                if area.length.is_some() || area.data.is_some() {
+                    return Err("areas hold either a process or data".to_string());
+                }
+                memory.push(Area::from(s(&build(child)?)));
+            }
+            None => {
+                let mut flat = Area::new();
+                flat.resize(area.length.unwrap_or_default().0);
+                for run in area.data.iter().flatten() {
+                    let run = unwords(run);
+                    let fits = match run.split_first() {
+                        Some((offset, words)) => offset
+                            .checked_add(words.len() as u64)
+                            .is_some_and(|end| end <= flat.len()),
+                        None => false,
+                    };
+                    if !fits {
+                        return Err("data runs past the end of the area".to_string());
+                    }
+                    flat.write(run[0], &run[1..]);
+                }
+                memory.push(flat);
+            }
+        }
+    }
+
+    Ok(Process {
+        header: Header {
+            status,
+            rec: text.rec.0,
+            gas: text.gas.0,
+            mem: text.mem.0,
+            ip: text.ip.0,
+        },
+        code,
+        stack: unwords(&text.stack),
+        map: unwords(&text.map),
+        memory,
+        calls: unwords(&text.calls),
+        opset: text.opset.0,
+        fault: text.fault.map(|(area, offset)| (area.0, offset.0)),
+        shares: text
+            .shares
+            .iter()
+            .map(|share| Share {
+                area: share.area.0,
+                parent: share.parent.0,
+                readonly: share.readonly,
+            })
+            .collect(),
+        outbox: unwords(&text.outbox),
+        inbox: unwords(&text.inbox),
+    })
+}
+
+/**
+Read a snapshot from the TOML written by to_text, possibly edited by hand
+*/
+pub fn from_text(text: &str) -> Result<Process, String> {
+    let parsed: ProcessText = ::toml::from_str(text).map_err(|e| e.to_string())?;
+    build(&parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Process {
+        let child = d(&[3, 0, 5, 6, 0, 3, 0, 0, 0, 6, 7, 0]);
+        let mut sparse = Area::new();
+        sparse.resize(1 << 40);
+        sparse.write(2047, &[1, 2, 3]);
+        Process {
+            header: Header {
+                status: Stati::YLD as u64,
+                rec: 0,
+                gas: u64::MAX,
+                mem: 1 << 63,
+                ip: 2,
+            },
+            code: vec![6, 42, 0, 9999],
+            stack: vec![1, u64::MAX],
+            map: vec![],
+            memory: vec![Area::from(s(&child)), sparse, Area::from(vec![0, 4, 0])],
+            calls: vec![3],
+            opset: OPSET,
+            fault: Some((1, 2)),
+            shares: vec![Share {
+                area: 0,
+                parent: 2,
+                readonly: true,
+            }],
+            outbox: vec![5],
+            inbox: vec![],
+        }
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let sharp = sample();
+        let text = to_text(&sharp);
+        assert!(text.contains("status = 'YLD'"));
+        assert!(text.contains("gas = '0xffffffffffffffff'"));
+        assert!(text.contains("[areas.process]"));
+        let back = from_text(&text).unwrap();
+        assert_eq!(s(&back), s(&sharp));
+    }
+
+    #[test]
+    fn hand_written_text_is_read() {
+        let text = "status = 1\ncode = \"\"\"\nPUSH 3 # three\nHALT\n\"\"\"\n\n\
+                    [[areas]]\nlength = 4\ndata = [[1, 7, 8]]\n";
+        let sharp = from_text(text).unwrap();
+        assert_eq!(sharp.header.status, 1);
+        assert_eq!(sharp.code, vec![6, 3, 0]);
+        assert_eq!(sharp.memory[0].to_vec(), vec![0, 7, 8, 0]);
+    }
+
+    #[test]
+    fn bad_text_is_rejected() {
+        assert!(from_text("gas = -1\n").is_err());
+        assert!(from_text("colour = 3\n").is_err());
+        assert!(from_text("[[areas]]\nlength = 2\ndata = [[1, 5, 6]]\n").is_err());
+        assert!(from_text("code = \"FLY\"\n").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use ops::*;
+use formats::*;
+use flow::*;
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    UnknownCode(u64),
+    Truncated,
+    BadTarget(u64),
+    StackUnderflow(u64, u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub ip: u64,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.problem {
+            Problem::UnknownCode(instr) => write!(f, "{}: unknown opcode {}", self.ip, instr),
+            Problem::Truncated => write!(f, "{}: immediate runs past the end of code", self.ip),
+            Problem::BadTarget(target) => {
+                write!(f, "{}: jump target {} is not an instruction", self.ip, target)
+            }
+            Problem::StackUnderflow(depth, req) => write!(
+                f,
+                "{}: stack may hold {} values, {} required",
+                self.ip, depth, req
+            ),
+        }
+    }
+}
+
+/**
+Statically check a code section of the latest opcode set entered at ip 0 with an empty stack
+*/
+#[allow(dead_code)]
+pub fn verify(code: &[u64]) -> Vec<Diagnostic> {
+    verify_from(code, OPSET, 0, 0)
+}
+
+/**
+Statically check the code of a snapshot from where it will resume
+*/
+pub fn verify_process(sharp: &Process) -> Vec<Diagnostic> {
+    verify_from(&sharp.code, sharp.opset, sharp.header.ip, sharp.stack.len() as u64)
+}
+
+fn verify_from(code: &[u64], set: u64, entry: u64, depth: u64) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    let starts = starts(code, set);
+    let on_boundary = |target: u64| target < code.len() as u64 && starts[target as usize];
+
+    for ip in 0..code.len() {
+        if !starts[ip] {
+            continue;
+        }
+        let problem = match lookup(code[ip], set) {
+            None => Some(Problem::UnknownCode(code[ip])),
+            Some(_) if decode(code, ip, set).is_none() => Some(Problem::Truncated),
+            Some(i) if i == IS::JUMP || i == IS::JZ || i == IS::CALL => {
+                static_target(code, &starts, ip)
+                    .filter(|t| !on_boundary(*t))
+                    .map(Problem::BadTarget)
+            }
+            Some(_) => None,
+        };
+        if let Some(problem) = problem {
+            diagnostics.push(Diagnostic {
+                ip: ip as u64,
+                problem,
+            });
+        }
+    }
+
+    // Smallest stack depth reachable at each instruction along any path
+    let mut mindepth: Vec<Option<u64>> = vec![None; code.len()];
+    let mut reported: Vec<bool> = vec![false; code.len()];
+    let mut work: Vec<(u64, u64)> = Vec::new();
+    if on_boundary(entry) {
+        work.push((entry, depth));
+    }
+
+    while let Some((ip, depth)) = work.pop() {
+        let ip = ip as usize;
+        match mindepth[ip] {
+            Some(known) if known <= depth => continue,
+            _ => mindepth[ip] = Some(depth),
+        }
+
+        let (i, reqs) = match decode(code, ip, set) {
+            Some(decoded) => decoded,
+            None => continue,
+        };
+
+        if (reqs.stack_req as u64) > depth && !reported[ip] {
+            reported[ip] = true;
+            diagnostics.push(Diagnostic {
+                ip: ip as u64,
+                problem: Problem::StackUnderflow(depth, reqs.stack_req as u64),
+            });
+        }
+
+        let after = (depth as i64 + reqs.addtl_mem as i64).max(0) as u64;
+        let next = (ip + reqs.length as usize) as u64;
+        let target = static_target(code, &starts, ip).filter(|t| on_boundary(*t));
+
+        match i {
+            IS::HALT | IS::RETURN | IS::RET => {}
+            IS::JUMP => {
+                if let Some(target) = target {
+                    work.push((target, after));
+                }
+            }
+            IS::JZ | IS::CALL => {
+                if let Some(target) = target {
+                    work.push((target, after));
+                }
+                if on_boundary(next) {
+                    work.push((next, after));
+                }
+            }
+            _ => {
+                if on_boundary(next) {
+                    work.push((next, after));
+                }
+            }
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.ip);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_leaves_the_count_on_the_stack() {
+        // PUSH 0, PUSH 1, PUSH 2, RUN, ADD, HALT
+        let diagnostics = verify(&[6, 0, 6, 1, 6, 2, 3, 23, 0]);
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                ip: 7,
+                problem: Problem::StackUnderflow(1, 2),
+            }]
+        );
+    }
+
+    #[test]
+    fn valid_code_has_no_diagnostics() {
+        // PUSH 1, PUSH 2, ADD, PUSH 8, CALL, HALT, RET
+        assert_eq!(verify(&[6, 1, 6, 2, 23, 6, 8, 33, 0, 34]), vec![]);
+    }
+
+    #[test]
+    fn broken_code_is_reported() {
+        let problem = |code: &[u64]| -> Vec<(u64, Problem)> {
+            verify(code).into_iter().map(|d| (d.ip, d.problem)).collect()
+        };
+        assert_eq!(problem(&[200]), vec![(0, Problem::UnknownCode(200))]);
+        assert_eq!(problem(&[0, 6]), vec![(1, Problem::Truncated)]);
+        // PUSH 1, JUMP into the immediate
+        assert_eq!(problem(&[6, 1, 4]), vec![(2, Problem::BadTarget(1))]);
+        // ADD on an empty stack
+        assert_eq!(problem(&[23, 0]), vec![(0, Problem::StackUnderflow(0, 2))]);
+    }
+
+    #[test]
+    fn opcodes_newer_than_the_opset_are_unknown() {
+        let mut sharp = d(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 34]);
+        sharp.opset = 0;
+        let diagnostics = verify_process(&sharp);
+        assert_eq!(diagnostics[0].problem, Problem::UnknownCode(34));
+    }
+}
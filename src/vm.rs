@@ -1,27 +1,208 @@
 use ops::*;
 use ops::num_traits::FromPrimitive;
 use formats::*;
+use verify::*;
+use area::*;
+use observer::*;
+
+// Maximum depth of the return stack used by CALL and RET
+pub const MAX_CALLS: usize = 1024;
+
+// Default limit on how many processes may be nested below the one passed to run
+pub const MAX_DEPTH: u64 = 64;
+
+/**
+Check that an area holds len words starting at offset
+*/
+fn span(state: &Process, area: u64, offset: u64, len: u64) -> Result<(usize, u64), Stati> {
+    if area >= state.memory.len() as u64 {
+        return Err(Stati::IVA);
+    }
+    match offset.checked_add(len) {
+        Some(end) if end <= state.memory[area as usize].len() => {
+            Ok((area as usize, offset))
+        }
+        _ => Err(Stati::IVO),
+    }
+}
+
+/**
+Like span, but the area must also be writable
+*/
+fn span_mut(state: &Process, area: u64, offset: u64, len: u64) -> Result<(usize, u64), Stati> {
+    let (area, offset) = span(state, area, offset, len)?;
+    if state.memory[area].readonly() {
+        return Err(Stati::ROA);
+    }
+    Ok((area, offset))
+}
 
 /**
-Run a snapshot until an exit or error occurs
+Check that the areas a child shares refer to distinct areas of the parent other than its own
 */
-pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
-    //println!("Length of binary: {0}", flat.len());
-    // Process, previously serialized length, rec index
-    let mut edges: Vec<u64> = vec![0];
-    let mut sizes: Vec<u64> = vec![s(&sharp).len() as u64];
-    let mut states: Vec<Process> = vec![sharp]; //d(&flat)
-
-    let statelen = states.len() - 1 as usize;
-    states[statelen].header.status = Stati::NOR as u64;
-    states[statelen].header.gas = gas;
-    states[statelen].header.mem = mem;
+fn lendable(parent: &Process, child: &Process) -> bool {
+    let own = parent.header.rec - 1;
+    child.shares.iter().enumerate().all(|(i, share)| {
+        share.area < child.memory.len() as u64
+            && share.parent < parent.memory.len() as u64
+            && share.parent != own
+            && child.shares[..i].iter().all(|other| {
+                other.parent != share.parent && other.area != share.area
+            })
+    })
+}
+
+/**
+Move the areas a child shares with its parent into the child while it runs
+*/
+fn lend(parent: &mut Process, child: &mut Process) {
+    for share in child.shares.iter() {
+        let lent = std::mem::replace(&mut parent.memory[share.parent as usize], Area::new());
+        let readonly = share.readonly || lent.readonly();
+        child.memory[share.area as usize] = lent;
+        child.memory[share.area as usize].set_readonly(readonly);
+    }
+}
+
+/**
+Return shared areas to the parent once the child stops
+*/
+fn reclaim(parent: &mut Process, child: &mut Process) {
+    for share in child.shares.iter() {
+        let mut lent = std::mem::replace(&mut child.memory[share.area as usize], Area::new());
+        lent.set_readonly(
+            parent
+                .shares
+                .iter()
+                .any(|own| own.area == share.parent && own.readonly),
+        );
+        parent.memory[share.parent as usize] = lent;
+    }
+}
+
+/**
+The process held by an area, if the area is laid out like a snapshot. Children carry their
+code, referenced code is only resolved when snapshots are read.
+*/
+fn nested(area: &Area) -> Option<Process> {
+    try_d_area(area)
+}
+
+/**
+Whether RUN may continue a child with this status: fresh, yielded, returned, too deep or out
+of memory or gas
+*/
+fn resumable(status: u64) -> bool {
+    status == Stati::NOR as u64
+        || status == Stati::YLD as u64
+        || status == Stati::RET as u64
+        || status == Stati::OOM as u64
+        || status == Stati::DEP as u64
+        || status == Stati::OOG as u64
+}
+
+/**
+Stop a process on an invalid memory access, remembering the address for diagnostics
+*/
+fn fault(state: &mut Process, status: Stati, area: u64, offset: u64) {
+    state.header.status = status as u64;
+    state.fault = Some((area, offset));
+}
+
+/**
+Run a snapshot until an exit or error occurs, with at most depth processes nested below it
+*/
+#[allow(dead_code)]
+pub fn run(sharp: Process, gas: u64, mem: u64, depth: u64, debug: bool) -> Process {
+    observe(sharp, gas, mem, depth, debug, &mut Silent)
+}
+
+/**
+Like run, reporting execution to an observer. Observers asking to pause are ignored,
+use resume for that.
+*/
+pub fn observe<O: Observer>(
+    sharp: Process,
+    gas: u64,
+    mem: u64,
+    depth: u64,
+    debug: bool,
+    observer: &mut O,
+) -> Process {
+    let mut nest = Nest::new(sharp, gas, mem);
+    drive(&mut nest, depth, debug, observer, false);
+    nest.root()
+}
+
+/**
+A root process with the children running below it, deserialized, and what they were charged
+so far. Only the running process is charged per instruction. Each level counts the steps and
+weight it and its descendants used, which is settled with the parent when it stops, and knows
+how many steps and how much weight its ancestors can still pay for and which of them runs out
+first.
+*/
+#[derive(Debug, Clone)]
+pub struct Nest {
+    pub states: Vec<Process>,
+    // Process, previously serialized length
+    sizes: Vec<u64>,
+    used: Vec<(u64, u64)>,
+    allowance: Vec<(u64, usize)>,
+    steps: Vec<(u64, usize)>,
+}
+
+impl Nest {
+    pub fn new(sharp: Process, gas: u64, mem: u64) -> Nest {
+        let mut sharp = sharp;
+        sharp.header.status = Stati::NOR as u64;
+        sharp.header.gas = gas;
+        sharp.header.mem = mem;
+        Nest {
+            sizes: vec![size(&sharp)],
+            states: vec![sharp],
+            used: vec![(0, 0)],
+            allowance: vec![(u64::MAX, 0)],
+            steps: vec![(u64::MAX, 0)],
+        }
+    }
+
+    pub fn root(mut self) -> Process {
+        self.states.swap_remove(0)
+    }
+}
+
+/**
+Continue running a nest until the root stops, returning true, or until the observer asks to
+pause before an instruction, returning false. A paused nest is left exactly as it was, nothing
+is serialized or settled early, so resuming it runs the same as if it had never paused.
+*/
+pub fn resume<O: Observer>(nest: &mut Nest, depth: u64, debug: bool, observer: &mut O) -> bool {
+    drive(nest, depth, debug, observer, true)
+}
+
+fn drive<O: Observer>(
+    nest: &mut Nest,
+    depth: u64,
+    debug: bool,
+    observer: &mut O,
+    pausable: bool,
+) -> bool {
+    let Nest {
+        ref mut states,
+        ref mut sizes,
+        ref mut used,
+        ref mut allowance,
+        ref mut steps,
+    } = *nest;
 
     loop {
+        let statelen = states.len() - 1;
+        let status = states[statelen].header.status;
+        let faulted = states[statelen].fault;
         let mut jump_back: i64 = -2;
         let blockret = {
             let instr: u64 = 0;
-            let ref mut state = states[statelen];
+            let state = &mut states[statelen];
             //println!("{:?} {:?}", state.header.gas, state.header.ip);
             if debug {
                 println!("{:?}", state.stack);
@@ -31,7 +212,7 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                 //&& state.header.status != REC
                 jump_back = (statelen as i64) - 1;
                 (
-                    instr.clone(),
+                    instr,
                     Requirement {
                         length: 0,
                         stack_req: 0,
@@ -43,7 +224,7 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                 state.header.status = Stati::OOC as u64;
                 jump_back = (statelen as i64) - 1;
                 (
-                    instr.clone(),
+                    instr,
                     Requirement {
                         length: 0,
                         stack_req: 0,
@@ -53,13 +234,13 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                 )
             } else {
                 let instr = state.code[state.header.ip as usize];
-                let decoded: Option<IS> = IS::from_u64(instr);
+                let decoded: Option<IS> = lookup(instr, state.opset);
                 match decoded {
                     None => {
                         state.header.status = Stati::UOC as u64;
                         jump_back = (statelen as i64) - 1;
                         (
-                            instr.clone(),
+                            instr,
                             Requirement {
                                 length: 0,
                                 stack_req: 0,
@@ -69,8 +250,8 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                         )
                     }
                     Some(i) => {
-                        let reqs = requirement(i);
-                        if state.header.ip + (reqs.length as u64) - 1 >= state.code.len() as u64 {
+                        let reqs = requirement_in(i, state.opset);
+                        if state.header.ip + (reqs.length as u64) > state.code.len() as u64 {
                             state.header.status = Stati::OOA as u64;
                             jump_back = (statelen as i64) - 1;
                         }
@@ -88,46 +269,95 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
         let instr: u64 = blockret.0;
         let reqs = blockret.1;
 
-        fn valid_area(index: u64, process: &Process) -> bool {
-            return index < process.memory.len() as u64;
+        if jump_back == -2 && pausable && observer.pause() {
+            return false;
         }
 
+        // Bulk memory instructions additionally cost per word they touch,
+        // growing an area has to fit into the memory budget before anything is allocated
+        let (words, reserve): (u64, u64) = if jump_back == -2 {
+            let state = &states[statelen];
+            let top = state.stack.len();
+            match lookup(instr, state.opset) {
+                Some(IS::MEMCOPY) | Some(IS::MEMCMP) => (state.stack[top - 1], 0),
+                Some(IS::MEMFILL) => (state.stack[top - 2], 0),
+                Some(IS::SENDAREA) => (state.stack[top - 1], 0),
+                Some(IS::ALLOC) => (0, state.stack[top - 1]),
+                _ => (0, 0),
+            }
+        } else {
+            (0, 0)
+        };
+        let extra = words.saturating_mul(WORD_GAS);
+        let step = 1u64.saturating_add(extra);
+        let weight = (reqs.gas_cost as u64).saturating_add(extra);
+
+        // Refuse what the process or an ancestor cannot pay for before any of it is done,
+        // charging the size the process may grow to
         if jump_back == -2 {
-            for psi in (0..states.len()).rev() {
-                let gascost = reqs.gas_cost as u64;
-                let memcost: u64 = gascost; //XXX(ps.1 + (reqs[2] as u64))*
+            let memcost = sizes[statelen].saturating_add(reserve).saturating_mul(weight);
+            let (allowed, binding) = allowance[statelen];
+            let (stepsallowed, stepsbinding) = steps[statelen];
 
-                if states[psi].header.mem < memcost {
-                    states[psi].header.status = Stati::OOM as u64;
-                    jump_back = (psi as i64) - 1;
+            let out = if used[statelen].1.saturating_add(weight) > allowed {
+                Some((binding, Stati::OOM as u64))
+            } else if used[statelen].0.saturating_add(step) > stepsallowed {
+                Some((stepsbinding, Stati::OOG as u64))
+            } else if states[statelen].header.mem < memcost {
+                Some((statelen, Stati::OOM as u64))
+            } else if states[statelen].header.gas < step {
+                Some((statelen, Stati::OOG as u64))
+            } else {
+                None
+            };
+            if let Some((level, stop)) = out {
+                states[level].header.status = stop;
+                if level != statelen {
+                    observer.status(&states[level], level, stop);
                 }
-
-                //p.header.mem -= memcost;
-                //p.header.gas -= gascost;
+                jump_back = (level as i64) - 1;
             }
         }
 
         if jump_back > -2 {
-            let mut serialized: Vec<u64> = Vec::new();
-            for psi in (0..states.len()).rev() {
-                if psi != 0 {
-                    states[statelen - psi].memory[edges[edges.len() - psi] as usize] = serialized;
-                    serialized = s(&states[states.len() - psi - 1]);
-                }
+            if states[statelen].header.status != status {
+                observer.status(&states[statelen], statelen, states[statelen].header.status);
+            }
 
-                if psi == 0 {
-                    return states[0].clone();
+            // Serialize stopped children back into their parents' areas
+            while states.len() as i64 - 1 > jump_back {
+                // The root stays in the nest
+                if states.len() == 1 {
+                    return true;
                 }
+                let mut child = states.pop().unwrap();
+                sizes.pop();
+                allowance.pop();
+                steps.pop();
+                let (childsteps, weight) = used.pop().unwrap();
+                observer.exit(&child, states.len());
 
-                if psi as i64 == jump_back - 1 {
-                    break;
-                }
+                let level = states.len() - 1;
+                let parent = &mut states[level];
+                parent.header.gas = parent.header.gas.saturating_sub(childsteps);
+                parent.header.mem = parent
+                    .header
+                    .mem
+                    .saturating_sub(sizes[level].saturating_mul(weight));
+                used[level].0 = used[level].0.saturating_add(childsteps);
+                used[level].1 = used[level].1.saturating_add(weight);
+
+                reclaim(parent, &mut child);
+                let area = (parent.header.rec - 1) as usize;
+                parent.memory[area] = Area::from(s(&child));
             }
+            continue;
         }
 
         let ie: IS = unsafe { std::mem::transmute(instr as i8) };
-        let ref mut state = states[statelen];
-        let stacklen: usize = state.stack.len() as usize;
+        observer.before(&states[statelen], statelen, ie);
+        let state = &mut states[statelen];
+        let stacklen: usize = state.stack.len();
         if debug {
             println!(
                 "INSTR: {:?} {:?}",
@@ -135,37 +365,90 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                 state.header.ip
             );
         }
-        if jump_back > -2 {
-            //pass
-        } else if ie == IS::RUN {
+        if ie == IS::RUN {
             let area = state.stack[stacklen - 3];
             let gas = state.stack[stacklen - 2];
             let mem = state.stack[stacklen - 1];
 
-            if valid_area(area, &states[statelen]) {
-                {
-                    if states[statelen].header.rec == 0 {
-                        let ref mut child = states[statelen];
-                        child.header.rec = area + 1;
+            match span_mut(state, area, 0, PREAMBLE) {
+                Err(status) => fault(state, status, area, 0),
+                Ok((area, _)) => {
+                    if state.header.rec == 0 {
+                        state.header.rec = area as u64 + 1;
 
-                        child.memory[area as usize][0] = Stati::NOR as u64;
-                        child.memory[area as usize][2] = gas;
-                        child.memory[area as usize][3] = mem;
+                        // Stopped children are left alone until they are reset
+                        if resumable(state.memory[area].get(STATUS)) {
+                            state.memory[area].set(STATUS, Stati::NOR as u64);
+                            state.memory[area].set(GAS, gas);
+                            state.memory[area].set(MEM, mem);
+
+                            // Refuse to start children that are malformed or fail static checks
+                            let valid = match nested(&state.memory[area]) {
+                                Some(child) => {
+                                    verify_process(&child).is_empty() && lendable(state, &child)
+                                }
+                                None => false,
+                            };
+                            if !valid {
+                                state.memory[area].set(STATUS, Stati::INV as u64);
+                            } else if statelen as u64 >= depth {
+                                state.memory[area].set(STATUS, Stati::DEP as u64);
+                            }
+                        }
+                    }
+                    let child = if state.memory[area].get(STATUS) == Stati::NOR as u64 {
+                        nested(&state.memory[area])
+                    } else {
+                        None
+                    };
+                    if let Some(mut child) = child {
+                        let childsize = size(&child);
+                        lend(state, &mut child);
+                        let own = (state.header.mem / sizes[statelen].max(1), statelen);
+                        let (allowed, binding) = allowance[statelen];
+                        let inherited = (allowed.saturating_sub(used[statelen].1), binding);
+                        allowance.push(if inherited.0 <= own.0 { inherited } else { own });
+                        let own = (state.header.gas, statelen);
+                        let (allowed, binding) = steps[statelen];
+                        let inherited = (allowed.saturating_sub(used[statelen].0), binding);
+                        steps.push(if inherited.0 <= own.0 { inherited } else { own });
+                        used.push((0, 0));
+                        sizes.push(childsize);
+                        observer.enter(&child, statelen + 1);
+                        states.push(child);
+                    } else if state.memory[area].get(STATUS) == Stati::NOR as u64 {
+                        // The area no longer holds a process
+                        state.header.rec = 0;
+                        fault(state, Stati::INV, area as u64, 0);
+                    } else {
+                        // The child stopped, continue after RUN
+                        state.header.rec = 0;
+                        state.stack.truncate(stacklen - 3);
+                        state.header.ip += reqs.length as u64;
+
+                        // Deliver what the child sent, followed by the number of words, even if 0,
+                        // from the set that lets children send on
+                        if delivers(state.opset) {
+                            let child =
+                                nested(&state.memory[area]).filter(|c| !c.outbox.is_empty());
+                            let count = match child {
+                                Some(mut child) => {
+                                    let count = child.outbox.len() as u64;
+                                    state.stack.append(&mut child.outbox);
+                                    state.memory[area] = Area::from(s(&child));
+                                    count
+                                }
+                                None => 0,
+                            };
+                            state.stack.push(count);
+                        }
                     }
-                }
-                if states[statelen].header.rec > 0
-                    && states[statelen].memory[area as usize][0] == Stati::NOR as u64
-                {
-                    states.push(d(&states[statelen].memory[area as usize]));
-                    edges.push(states[statelen].header.rec);
-                    sizes.push(states[statelen].memory[area as usize].len() as u64);
-                } else {
-                    states[statelen].header.rec = 0;
                 }
             }
         } else {
-            let ref mut state = states[statelen];
+            let state = &mut states[statelen];
             let mut jump: bool = false;
+            let mut grown: i64 = 0;
             if ie == IS::HALT {
                 println!("HALT");
                 state.header.status = Stati::HLT as u64;
@@ -175,6 +458,20 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                 jump = true;
             } else if ie == IS::YIELD {
                 state.header.status = Stati::YLD as u64;
+            } else if ie == IS::CALL {
+                if state.calls.len() >= MAX_CALLS {
+                    state.header.status = Stati::RSO as u64;
+                } else {
+                    state.calls.push(state.header.ip + reqs.length as u64);
+                    state.header.ip = state.stack.pop().unwrap();
+                }
+                jump = true;
+            } else if ie == IS::RET {
+                match state.calls.pop() {
+                    Some(ip) => state.header.ip = ip,
+                    None => state.header.status = Stati::RSU as u64,
+                }
+                jump = true;
             } else if ie == IS::JUMP {
                 state.header.ip = state.stack[state.stack.len() - 1];
                 state.stack.pop();
@@ -188,25 +485,16 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                     state.stack.pop();
                     state.stack.pop();
                 }
-            } else if ie == IS::DUP {
-                state.stack.push(state.stack[state.stack.len() - 1]);
             //memory effect has to be applied to all parent states!
             //check resources after decision?
             } else if ie == IS::PUSH {
                 let value = state.code[(state.header.ip + 1) as usize];
                 state.stack.push(value);
             } else if ie == IS::POP {
-                if state.stack.len() > 0 {
-                    state.stack.pop();
-                }
+                state.stack.pop();
             } else if ie == IS::DUP {
-                let last = state.stack.last();
-                match last {
-                    Some(last) => {
-                        let truelast = last.clone();
-                        state.stack.push(truelast);
-                    }
-                    None => {}
+                if let Some(&last) = state.stack.last() {
+                    state.stack.push(last);
                 }
             } else if ie == IS::ADD {
                 if state.stack.len() >= 2 {
@@ -220,8 +508,36 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                     let a = state.stack.pop().unwrap();
                     state.stack.push(a.wrapping_sub(b));
                 }
+            } else if ie == IS::AND
+                || ie == IS::OR
+                || ie == IS::XOR
+                || ie == IS::SHL
+                || ie == IS::SHR
+                || ie == IS::SAR
+                || ie == IS::LT
+                || ie == IS::GT
+                || ie == IS::EQ
+                || ie == IS::SLT
+                || ie == IS::SGT
+            {
+                let b = state.stack.pop().unwrap();
+                let a = state.stack.pop().unwrap();
+                let result = match ie {
+                    IS::AND => a & b,
+                    IS::OR => a | b,
+                    IS::XOR => a ^ b,
+                    IS::SHL => if b < 64 { a << b } else { 0 },
+                    IS::SHR => if b < 64 { a >> b } else { 0 },
+                    IS::SAR => ((a as i64) >> b.min(63)) as u64,
+                    IS::LT => (a < b) as u64,
+                    IS::GT => (a > b) as u64,
+                    IS::EQ => (a == b) as u64,
+                    IS::SLT => ((a as i64) < (b as i64)) as u64,
+                    _ => ((a as i64) > (b as i64)) as u64,
+                };
+                state.stack.push(result);
             } else if ie == IS::NOT {
-                if state.stack.len() >= 1 {
+                if !state.stack.is_empty() {
                     let value = state.stack.pop().unwrap();
                     if value == 0 {
                         state.stack.push(1);
@@ -232,38 +548,295 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
             } else if ie == IS::MEMORYLEN {
                 state.stack.push(state.memory.len() as u64);
             } else if ie == IS::AREALEN {
-                let area = state.stack[stacklen - 1] as usize;
-                state.stack[stacklen - 1 as usize] = state.memory[area].len() as u64;
+                let area = state.stack[stacklen - 1];
+                match span(state, area, 0, 0) {
+                    Ok((area, _)) => state.stack[stacklen - 1] = state.memory[area].len(),
+                    Err(status) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
+                }
             } else if ie == IS::READ {
-                if state.stack.len() >= 2 {
-                    let offset = state.stack.pop().unwrap() as usize;
-                    let area = state.stack.pop().unwrap() as usize;
-                    if area < state.memory.len() && offset < state.memory[area].len() {
-                        state.stack.push(state.memory[area][offset]);
+                let area = state.stack[stacklen - 2];
+                let offset = state.stack[stacklen - 1];
+                match span(state, area, offset, 1) {
+                    Ok((area, offset)) => {
+                        state.stack.truncate(stacklen - 2);
+                        state.stack.push(state.memory[area].get(offset));
+                    }
+                    Err(status) => {
+                        fault(state, status, area, offset);
+                        jump = true;
                     }
                 }
             } else if ie == IS::WRITE {
-                if state.stack.len() >= 3 {
-                    let value = state.stack.pop().unwrap();
-                    let offset = state.stack.pop().unwrap() as usize;
-                    let area = state.stack.pop().unwrap() as usize;
-                    if area < state.memory.len() && offset < state.memory[area].len() {
-                        state.memory[area][offset] = value;
+                let area = state.stack[stacklen - 3];
+                let offset = state.stack[stacklen - 2];
+                let value = state.stack[stacklen - 1];
+                match span_mut(state, area, offset, 1) {
+                    Ok((area, offset)) => {
+                        state.stack.truncate(stacklen - 3);
+                        state.memory[area].set(offset, value);
+                    }
+                    Err(status) => {
+                        fault(state, status, area, offset);
+                        jump = true;
                     }
                 }
             } else if ie == IS::AREA {
-                state.memory.push(Vec::with_capacity(1024)); //XXX Vec::new()
+                state.memory.push(Area::new());
+                observer.alloc(state, statelen, state.memory.len() as u64 - 1, 0);
             } else if ie == IS::ALLOC {
-                let size = state.stack.pop().unwrap();
-                let area = state.stack.pop().unwrap() as usize;
-                for _i in 0..size {
-                    state.memory[area].push(0); //XXX should use resize
+                let area = state.stack[stacklen - 2];
+                let size = state.stack[stacklen - 1];
+                match span_mut(state, area, 0, 0) {
+                    // Lengths from SPARSE on cannot be serialized
+                    Ok((area, _)) => match state.memory[area].len().checked_add(size) {
+                        Some(arealen) if arealen < SPARSE => {
+                            state.stack.truncate(stacklen - 2);
+                            state.memory[area].resize(arealen);
+                            grown = size as i64;
+                            observer.alloc(state, statelen, area as u64, arealen);
+                        }
+                        _ => {
+                            fault(state, Stati::IVO, area as u64, size);
+                            jump = true;
+                        }
+                    },
+                    Err(status) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
                 }
             } else if ie == IS::DEALLOC {
-                let size = state.stack.pop().unwrap();
-                let area = state.stack.pop().unwrap() as usize;
-                for _i in 0..size {
-                    state.memory[area].pop(); //XXX should use resize
+                let area = state.stack[stacklen - 2];
+                let size = state.stack[stacklen - 1];
+                match span_mut(state, area, 0, size) {
+                    Ok((area, _)) => {
+                        state.stack.truncate(stacklen - 2);
+                        let arealen = state.memory[area].len();
+                        state.memory[area].resize(arealen - size);
+                        grown = -(size as i64);
+                        observer.alloc(state, statelen, area as u64, arealen - size);
+                    }
+                    Err(status) => {
+                        fault(state, status, area, size);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::MEMCOPY {
+                let (sa, so) = (state.stack[stacklen - 5], state.stack[stacklen - 4]);
+                let (da, doff) = (state.stack[stacklen - 3], state.stack[stacklen - 2]);
+                match (span(state, sa, so, words), span_mut(state, da, doff, words)) {
+                    (Ok((sa, so)), Ok((da, doff))) => {
+                        let copied = state.memory[sa].read(so, words);
+                        state.memory[da].write(doff, &copied);
+                        state.stack.truncate(stacklen - 5);
+                    }
+                    (Err(status), _) => {
+                        fault(state, status, sa, so);
+                        jump = true;
+                    }
+                    (_, Err(status)) => {
+                        fault(state, status, da, doff);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::MEMFILL {
+                let (area, offset) = (state.stack[stacklen - 4], state.stack[stacklen - 3]);
+                let value = state.stack[stacklen - 1];
+                match span_mut(state, area, offset, words) {
+                    Ok((area, offset)) => {
+                        state.memory[area].fill(offset, words, value);
+                        state.stack.truncate(stacklen - 4);
+                    }
+                    Err(status) => {
+                        fault(state, status, area, offset);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::MEMCMP {
+                let (aa, ao) = (state.stack[stacklen - 5], state.stack[stacklen - 4]);
+                let (ba, bo) = (state.stack[stacklen - 3], state.stack[stacklen - 2]);
+                match (span(state, aa, ao, words), span(state, ba, bo, words)) {
+                    (Ok((aa, ao)), Ok((ba, bo))) => {
+                        let a = state.memory[aa].read(ao, words);
+                        let order = a.cmp(&state.memory[ba].read(bo, words));
+                        state.stack.truncate(stacklen - 5);
+                        state.stack.push(order as i64 as u64);
+                    }
+                    (Err(status), _) => {
+                        fault(state, status, aa, ao);
+                        jump = true;
+                    }
+                    (_, Err(status)) => {
+                        fault(state, status, ba, bo);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::SHARE {
+                let childarea = state.stack[stacklen - 3];
+                let area = state.stack[stacklen - 2];
+                let readonly = state.stack[stacklen - 1] != 0;
+                let target = span_mut(state, childarea, 0, PREAMBLE).and_then(|(c, _)| {
+                    nested(&state.memory[c]).map(|child| (c, child)).ok_or(Stati::INV)
+                });
+                match (target, span(state, area, 0, 0)) {
+                    (Ok((c, mut child)), Ok((a, _))) if c != a => {
+                        let index = match child.shares.iter().position(|sh| sh.parent == a as u64) {
+                            Some(existing) => {
+                                child.shares[existing].readonly = readonly;
+                                child.shares[existing].area
+                            }
+                            None => {
+                                child.memory.push(Area::new());
+                                child.shares.push(Share {
+                                    area: child.memory.len() as u64 - 1,
+                                    parent: a as u64,
+                                    readonly,
+                                });
+                                child.memory.len() as u64 - 1
+                            }
+                        };
+                        state.memory[c] = Area::from(s(&child));
+                        state.stack.truncate(stacklen - 3);
+                        state.stack.push(index);
+                    }
+                    (Err(status), _) => {
+                        fault(state, status, childarea, 0);
+                        jump = true;
+                    }
+                    (_, Err(status)) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
+                    _ => {
+                        fault(state, Stati::IVA, area, 0);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::SEND {
+                let value = state.stack.pop().unwrap();
+                state.outbox.push(value);
+                state.header.status = Stati::YLD as u64;
+            } else if ie == IS::SENDAREA {
+                let area = state.stack[stacklen - 3];
+                let offset = state.stack[stacklen - 2];
+                let len = state.stack[stacklen - 1];
+                match span(state, area, offset, len) {
+                    Ok((area, offset)) => {
+                        let words = state.memory[area].read(offset, len);
+                        state.outbox.extend(words);
+                        state.stack.truncate(stacklen - 3);
+                        state.header.status = Stati::YLD as u64;
+                    }
+                    Err(status) => {
+                        fault(state, status, area, offset);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::REPLY {
+                let area = state.stack[stacklen - 2];
+                let value = state.stack[stacklen - 1];
+                let target = span_mut(state, area, 0, PREAMBLE).and_then(|(a, _)| {
+                    nested(&state.memory[a]).map(|child| (a, child)).ok_or(Stati::INV)
+                });
+                match target {
+                    Ok((area, mut child)) => {
+                        child.inbox.push(value);
+                        state.memory[area] = Area::from(s(&child));
+                        state.stack.truncate(stacklen - 2);
+                    }
+                    Err(status) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::RECV {
+                if state.inbox.is_empty() {
+                    state.stack.push(0);
+                    state.stack.push(0);
+                } else {
+                    let value = state.inbox.remove(0);
+                    state.stack.push(value);
+                    state.stack.push(1);
+                }
+            } else if ie == IS::INSPECT {
+                let area = state.stack[stacklen - 2];
+                let field = state.stack[stacklen - 1];
+                match span(state, area, 0, PREAMBLE) {
+                    Ok((area, _)) if field < PREAMBLE => {
+                        state.stack.truncate(stacklen - 2);
+                        state.stack.push(state.memory[area].get(field));
+                    }
+                    Ok(_) => {
+                        fault(state, Stati::IVO, area, field);
+                        jump = true;
+                    }
+                    Err(status) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::PEEK {
+                let area = state.stack[stacklen - 1];
+                match span(state, area, 0, PREAMBLE) {
+                    Ok((area, _)) => {
+                        let child = &state.memory[area];
+                        let depth = child.get(STACKLEN);
+                        let top = (child.get(CODELEN) & !CODEFLAGS)
+                            .checked_add(depth)
+                            .and_then(|end| end.checked_add(PREAMBLE));
+                        match top {
+                            Some(top) => {
+                                state.stack[stacklen - 1] = if depth > 0 && top <= child.len() {
+                                    child.get(top - 1)
+                                } else {
+                                    0
+                                };
+                            }
+                            None => {
+                                fault(state, Stati::IVO, area as u64, STACKLEN);
+                                jump = true;
+                            }
+                        }
+                    }
+                    Err(status) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::KILL {
+                let area = state.stack[stacklen - 1];
+                match span_mut(state, area, 0, PREAMBLE) {
+                    Ok((area, _)) => {
+                        state.memory[area].set(STATUS, Stati::KIL as u64);
+                        state.stack.truncate(stacklen - 1);
+                    }
+                    Err(status) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
+                }
+            } else if ie == IS::RESET {
+                let area = state.stack[stacklen - 1];
+                let target = span_mut(state, area, 0, PREAMBLE).and_then(|(a, _)| {
+                    nested(&state.memory[a]).map(|child| (a, child)).ok_or(Stati::INV)
+                });
+                match target {
+                    Ok((area, mut child)) => {
+                        child.header.status = Stati::NOR as u64;
+                        child.header.ip = 0;
+                        child.stack.clear();
+                        child.calls.clear();
+                        child.fault = None;
+                        state.memory[area] = Area::from(s(&child));
+                        state.stack.truncate(stacklen - 1);
+                    }
+                    Err(status) => {
+                        fault(state, status, area, 0);
+                        jump = true;
+                    }
                 }
             } else if ie == IS::ROT2 {
                 //stack length should already be checked!
@@ -291,14 +864,418 @@ pub fn run(sharp: Process, gas: u64, mem: u64, debug: bool) -> Process {
                 }
                 */
             }
-            let _stackdiff: i64 = ((states[(states.len() - 1) as usize].stack.len() as i64)
-                - (stacklen as i64)) as i64;
-            let stateslen = states.len();
-            for i in 0..states.len() {
-                states[stateslen - i - 1].header.gas -= 1; //reqs[2] as u64;
-                let memcost: u64 = (sizes[states.len() - i - 1]) * (reqs.gas_cost as u64); //stackdiff
-                states[stateslen - i - 1].header.mem -= memcost;
+            // Keep the charged size in step with areas growing and shrinking
+            sizes[statelen] = (sizes[statelen] as i64 + grown) as u64;
+            let header = &mut states[statelen].header;
+            header.gas = header.gas.saturating_sub(step); //reqs[2] as u64;
+            let memcost: u64 = sizes[statelen].saturating_mul(weight); //stackdiff
+            header.mem = header.mem.saturating_sub(memcost);
+            used[statelen].0 = used[statelen].0.saturating_add(step);
+            used[statelen].1 = used[statelen].1.saturating_add(weight);
+        }
+
+        let state = &states[statelen];
+        observer.after(state, statelen, ie);
+        if state.header.status != status {
+            if state.header.status == Stati::YLD as u64 {
+                observer.yielded(state, statelen);
             }
+            observer.status(state, statelen, state.header.status);
+        }
+        if state.fault != faulted {
+            if let Some((area, offset)) = state.fault {
+                observer.fault(state, statelen, area, offset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(code: &[u64], areas: &[Vec<u64>]) -> Process {
+        let mut flat = vec![0, 0, 0, 0, 0, code.len() as u64, 0, 0, areas.len() as u64];
+        flat.extend(code);
+        for area in areas {
+            flat.push(area.len() as u64);
+            flat.extend(area);
+        }
+        let mut sharp = d(&flat);
+        sharp.opset = OPSET;
+        sharp
+    }
+
+    fn exec(sharp: Process) -> Process {
+        run(sharp, 1_000_000, 1_000_000, MAX_DEPTH, false)
+    }
+
+    fn child(code: &[u64]) -> Vec<u64> {
+        s(&process(code, &[]))
+    }
+
+    // PUSH area, PUSH gas, PUSH mem, RUN
+    const START: [u64; 7] = [6, 0, 6, 10_000, 6, 10_000, 3];
+
+    #[test]
+    fn parent_continues_after_child_halts() {
+        let mut code = START.to_vec();
+        code.extend(&[6, 7, 0]);
+        let done = exec(process(&code, &[child(&[6, 5, 0])]));
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.stack, vec![0, 7]);
+        assert_eq!(done.header.rec, 0);
+        let inner = d(&done.memory[0].to_vec());
+        assert_eq!(inner.header.status, Stati::HLT as u64);
+        assert_eq!(inner.stack, vec![5]);
+    }
+
+    #[test]
+    fn yielded_child_resumes_on_next_run() {
+        let mut code = START.to_vec();
+        code.extend(&START);
+        code.push(0);
+        let done = exec(process(&code, &[child(&[6, 1, 2, 6, 2, 0])]));
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.stack, vec![0, 0]);
+        let inner = d(&done.memory[0].to_vec());
+        assert_eq!(inner.header.status, Stati::HLT as u64);
+        assert_eq!(inner.stack, vec![1, 2]);
+    }
+
+    #[test]
+    fn run_pushes_sent_words_and_their_count() {
+        let mut code = START.to_vec();
+        code.extend(&START);
+        code.push(0);
+        // PUSH 5, SEND, HALT
+        let done = exec(process(&code, &[child(&[6, 5, 54, 0])]));
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.stack, vec![5, 1, 0]);
+    }
+
+    #[test]
+    fn children_can_allocate_more_than_the_host_could_serialize_in_full() {
+        // AREA, PUSH 0, PUSH 2^40, ALLOC, HALT
+        let grow = child(&[19, 6, 0, 6, 1 << 40, 21, 0]);
+        let mut code = vec![6, 0, 6, 10_000, 6, 10_000_000_000_000_000, 3];
+        code.push(0);
+        let mem = 10_000_000_000_000_000;
+        let done = run(process(&code, &[grow]), 1_000_000, mem, MAX_DEPTH, false);
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert!(done.memory[0].len() < 100);
+        let inner = d(&done.memory[0].to_vec());
+        assert_eq!(inner.header.status, Stati::HLT as u64);
+        assert_eq!(inner.memory[0].len(), 1 << 40);
+    }
+
+    #[test]
+    fn alloc_past_the_serializable_length_is_refused() {
+        // AREA, PUSH 0, PUSH 2^63, ALLOC
+        let code = [19, 6, 0, 6, SPARSE, 21, 0];
+        let done = run(process(&code, &[]), 1_000, u64::MAX, MAX_DEPTH, false);
+        assert_eq!(done.header.status, Stati::OOM as u64);
+        assert_eq!(done.memory[0].len(), 0);
+    }
+
+    #[test]
+    fn bulk_memory_is_refused_before_touching_memory() {
+        // AREA, ALLOC 2^26 words, MEMFILL all of them with 7
+        let code = [19, 6, 0, 6, 1 << 26, 21, 6, 0, 6, 0, 6, 1 << 26, 6, 7, 47, 0];
+        let done = run(process(&code, &[]), 1_000, 1 << 34, MAX_DEPTH, false);
+        assert_eq!(done.header.status, Stati::OOM as u64);
+        assert_eq!(done.memory[0].len(), 1 << 26);
+        assert_eq!(done.memory[0].allocated(), 0);
+        assert_eq!(done.stack, vec![0, 0, 1 << 26, 7]);
+    }
+
+    #[test]
+    fn steps_past_the_gas_are_refused() {
+        // PUSH 1, PUSH 2, ADD, HALT
+        let done = run(process(&[6, 1, 6, 2, 23, 0], &[]), 2, 1_000, MAX_DEPTH, false);
+        assert_eq!(done.header.status, Stati::OOG as u64);
+        assert_eq!(done.stack, vec![1, 2]);
+
+        // A child given more gas than its parent has stops its parent
+        let mut code = START.to_vec();
+        code.push(0);
+        let spin = child(&[6, 0, 4]);
+        let done = run(process(&code, &[spin]), 100, 1_000_000, MAX_DEPTH, false);
+        assert_eq!(done.header.status, Stati::OOG as u64);
+        assert_eq!(done.header.gas, 0);
+    }
+
+    // A preamble claiming more code than the area holds
+    const BROKEN: [u64; 9] = [0, 0, 0, 0, 0, 1000, 0, 0, 0];
+
+    #[test]
+    fn running_a_malformed_child_marks_it_invalid() {
+        let mut code = START.to_vec();
+        code.push(0);
+        let done = exec(process(&code, &[BROKEN.to_vec()]));
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.memory[0].get(STATUS), Stati::INV as u64);
+    }
+
+    #[test]
+    fn malformed_children_fault_instead_of_panicking() {
+        // SHARE, REPLY and RESET on area 0
+        let cases: [&[u64]; 3] = [&[6, 0, 6, 1, 6, 0, 49, 0], &[6, 0, 6, 5, 56, 0], &[6, 0, 53, 0]];
+        for code in cases.iter() {
+            let done = exec(process(code, &[BROKEN.to_vec(), vec![]]));
+            assert_eq!(done.header.status, Stati::INV as u64);
+            assert_eq!(done.fault, Some((0, 0)));
+        }
+    }
+
+    // Share area 1 with the child in area 0, read-only or not, then run the child
+    fn sharing(readonly: u64, code: &[u64]) -> Process {
+        // PUSH 0, PUSH 1, PUSH readonly, SHARE, POP
+        let mut parent = vec![6, 0, 6, 1, 6, readonly, 49, 7];
+        parent.extend(&START);
+        parent.push(0);
+        exec(process(&parent, &[child(code), vec![1, 2, 3]]))
+    }
+
+    #[test]
+    fn read_only_shared_areas_reject_writes() {
+        // PUSH 0, PUSH 0, PUSH 9, WRITE, HALT
+        let write = [6, 0, 6, 0, 6, 9, 18, 0];
+        let done = sharing(1, &write);
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        let inner = try_d_area(&done.memory[0]).unwrap();
+        assert_eq!(inner.header.status, Stati::ROA as u64);
+        assert_eq!(inner.fault, Some((0, 0)));
+        assert_eq!(done.memory[1].to_vec(), vec![1, 2, 3]);
+        assert!(!done.memory[1].readonly());
+
+        let done = sharing(0, &write);
+        let inner = try_d_area(&done.memory[0]).unwrap();
+        assert_eq!(inner.header.status, Stati::HLT as u64);
+        assert_eq!(done.memory[1].to_vec(), vec![9, 2, 3]);
+
+        // PUSH 0, PUSH 2, READ, HALT
+        let done = sharing(1, &[6, 0, 6, 2, 17, 0]);
+        let inner = try_d_area(&done.memory[0]).unwrap();
+        assert_eq!(inner.header.status, Stati::HLT as u64);
+        assert_eq!(inner.stack, vec![3]);
+    }
+
+    // Run a child that allocates an area and yields, then follow up with code
+    fn controlling(code: &[u64]) -> (Process, Process) {
+        // RUN area 0, POP the count
+        let mut parent = START.to_vec();
+        parent.push(7);
+        parent.extend(code);
+        parent.push(0);
+        // AREA, YIELD, HALT
+        let done = exec(process(&parent, &[child(&[19, 2, 0])]));
+        let inner = try_d_area(&done.memory[0]).unwrap();
+        (done, inner)
+    }
+
+    #[test]
+    fn killed_children_stay_where_they_stopped() {
+        // PUSH 0, KILL, then RUN again and POP the count
+        let mut code = vec![6, 0, 52];
+        code.extend(&START);
+        code.push(7);
+        let (done, inner) = controlling(&code);
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert!(done.stack.is_empty());
+        assert_eq!(inner.header.status, Stati::KIL as u64);
+        assert_eq!(inner.header.ip, 2);
+        assert_eq!(inner.memory.len(), 1);
+    }
+
+    #[test]
+    fn reset_children_start_over_with_their_memory() {
+        // PUSH 0, RESET
+        let (done, inner) = controlling(&[6, 0, 53]);
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(inner.header.status, Stati::NOR as u64);
+        assert_eq!(inner.header.ip, 0);
+        assert!(inner.stack.is_empty() && inner.calls.is_empty());
+        assert_eq!(inner.memory.len(), 1);
+
+        // PUSH 0, RESET, then RUN again, which allocates a second area
+        let mut code = vec![6, 0, 53];
+        code.extend(&START);
+        code.push(7);
+        let (_, inner) = controlling(&code);
+        assert_eq!(inner.header.status, Stati::YLD as u64);
+        assert_eq!(inner.memory.len(), 2);
+    }
+
+    #[test]
+    fn inspecting_what_is_not_there_faults() {
+        // PUSH 0, PUSH STATUS, INSPECT
+        let (done, _) = controlling(&[6, 0, 6, STATUS, 50]);
+        assert_eq!(done.stack, vec![Stati::YLD as u64]);
+
+        // PUSH 1, PUSH STATUS, INSPECT
+        let (done, _) = controlling(&[6, 1, 6, STATUS, 50]);
+        assert_eq!(done.header.status, Stati::IVA as u64);
+        assert_eq!(done.fault, Some((1, 0)));
+
+        // PUSH 0, PUSH PREAMBLE, INSPECT
+        let (done, _) = controlling(&[6, 0, 6, PREAMBLE, 50]);
+        assert_eq!(done.header.status, Stati::IVO as u64);
+        assert_eq!(done.fault, Some((0, PREAMBLE)));
+    }
+
+    #[test]
+    fn peek_faults_when_the_stack_end_overflows() {
+        let mut broken = BROKEN.to_vec();
+        broken[STACKLEN as usize] = u64::MAX;
+        // PUSH 0, PEEK
+        let done = exec(process(&[6, 0, 51, 0], &[broken]));
+        assert_eq!(done.header.status, Stati::IVO as u64);
+        assert_eq!(done.fault, Some((0, STACKLEN)));
+    }
+
+    #[test]
+    fn run_before_sending_leaves_no_count() {
+        // PUSH 7, RUN area 0, HALT: only the count separates the two sets
+        let mut code = vec![6, 7];
+        code.extend(&START);
+        code.push(0);
+        let mut older = process(&code, &[]);
+        older.opset = opset(IS::SEND) - 1;
+        let mut halting = process(&[6, 5, 0], &[]);
+        halting.opset = older.opset;
+        older.memory.push(Area::from(s(&halting)));
+
+        let done = exec(older.clone());
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.stack, vec![7]);
+        assert!(verify_process(&older).is_empty());
+
+        let done = exec(process(&code, &[s(&halting)]));
+        assert_eq!(done.stack, vec![7, 0]);
+    }
+
+    // Statuses of the processes nested through area 0, from the root down
+    fn chain(levels: usize) -> Vec<u64> {
+        // PUSH 0, PUSH gas, PUSH mem, RUN, HALT wrapped around a process that only halts
+        let code = [6, 0, 6, 1 << 40, 6, 1 << 60, 3, 0];
+        let mut inner = process(&[0], &[]);
+        for _level in 0..levels {
+            inner = process(&code, &[s(&inner)]);
+        }
+        let mut sharp = run(inner, 1 << 40, 1 << 62, MAX_DEPTH, false);
+        let mut stati = vec![sharp.header.status];
+        while let Some(child) = sharp.memory.first().and_then(try_d_area) {
+            stati.push(child.header.status);
+            sharp = child;
+        }
+        stati
+    }
+
+    #[test]
+    fn nesting_stops_at_the_maximum_depth() {
+        let hlt = Stati::HLT as u64;
+        assert_eq!(chain(MAX_DEPTH as usize), vec![hlt; MAX_DEPTH as usize + 1]);
+
+        let mut stati = vec![hlt; MAX_DEPTH as usize + 1];
+        stati.push(Stati::DEP as u64);
+        assert_eq!(chain(MAX_DEPTH as usize + 1), stati);
+    }
+
+    #[test]
+    fn child_with_malformed_compact_code_is_invalid() {
+        let mut code = START.to_vec();
+        code.push(0);
+        let broken = vec![0, 0, 0, 0, 0, COMPACT | 2, 0, 0, 0, 100, 0];
+        let done = exec(process(&code, &[broken]));
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.memory[0].get(STATUS), Stati::INV as u64);
+    }
+
+    #[test]
+    fn call_returns_after_the_call() {
+        // PUSH 6, CALL, PUSH 9, HALT, PUSH 5, RET
+        let done = exec(process(&[6, 6, 33, 6, 9, 0, 6, 5, 34], &[]));
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.stack, vec![5, 9]);
+        assert!(done.calls.is_empty());
+    }
+
+    #[test]
+    fn unbalanced_calls_fault() {
+        let done = exec(process(&[34], &[]));
+        assert_eq!(done.header.status, Stati::RSU as u64);
+        // PUSH 0, CALL
+        let done = exec(process(&[6, 0, 33], &[]));
+        assert_eq!(done.header.status, Stati::RSO as u64);
+        assert_eq!(done.calls.len(), MAX_CALLS);
+    }
+
+    #[test]
+    fn bitwise_and_comparison_results() {
+        let minus = |v: i64| v as u64;
+        let cases = [
+            (35, 12, 10, 8),
+            (36, 12, 10, 14),
+            (37, 12, 10, 6),
+            (38, 1, 3, 8),
+            (38, 1, 64, 0),
+            (39, 16, 2, 4),
+            (39, u64::MAX, 64, 0),
+            (40, minus(-8), 1, minus(-4)),
+            (40, minus(-1), 100, minus(-1)),
+            (41, minus(-1), 0, 0),
+            (42, 2, 1, 1),
+            (43, 3, 3, 1),
+            (44, minus(-1), 0, 1),
+            (45, minus(-1), 0, 0),
+        ];
+        for &(op, a, b, expected) in cases.iter() {
+            let done = exec(process(&[6, a, 6, b, op, 0], &[]));
+            assert_eq!(done.header.status, Stati::HLT as u64);
+            assert_eq!(done.stack, vec![expected], "opcode {}", op);
         }
     }
+
+    #[test]
+    fn bulk_memory_copies_fills_and_compares() {
+        let code = [
+            // MEMCOPY 3 words from area 0 offset 1 to area 1 offset 0
+            6, 0, 6, 1, 6, 1, 6, 0, 6, 3, 46,
+            // MEMFILL 1 word of area 1 at offset 3 with 7
+            6, 1, 6, 3, 6, 1, 6, 7, 47,
+            // MEMCMP 3 words of area 0 offset 1 with area 1 offset 0, then offset 0 with 0
+            6, 0, 6, 1, 6, 1, 6, 0, 6, 3, 48,
+            6, 0, 6, 0, 6, 1, 6, 0, 6, 2, 48,
+            0,
+        ];
+        let done = exec(process(&code, &[vec![1, 2, 3, 4], vec![0; 4]]));
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.memory[1].to_vec(), vec![2, 3, 4, 7]);
+        assert_eq!(done.stack, vec![0, u64::MAX]);
+    }
+
+    #[test]
+    fn bulk_memory_past_the_area_faults() {
+        // MEMFILL 2 words of area 0 at offset 3
+        let done = exec(process(&[6, 0, 6, 3, 6, 2, 6, 7, 47, 0], &[vec![0; 4]]));
+        assert_eq!(done.header.status, Stati::IVO as u64);
+        assert_eq!(done.fault, Some((0, 3)));
+        assert_eq!(done.memory[0].to_vec(), vec![0; 4]);
+        // MEMCOPY from area 2
+        let done = exec(process(&[6, 2, 6, 0, 6, 0, 6, 0, 6, 1, 46, 0], &[vec![0; 4]]));
+        assert_eq!(done.header.status, Stati::IVA as u64);
+        assert_eq!(done.fault, Some((2, 0)));
+    }
+
+    #[test]
+    fn running_a_huge_sparse_area_marks_it_invalid() {
+        // AREA, ALLOC 2^40 words, RUN it
+        let code = [19, 6, 0, 6, 1 << 40, 21, 6, 0, 6, 10, 6, 10, 3, 0];
+        let mem = 10_000_000_000_000_000;
+        let done = run(process(&code, &[]), 1_000, mem, MAX_DEPTH, false);
+        assert_eq!(done.header.status, Stati::HLT as u64);
+        assert_eq!(done.memory[0].len(), 1 << 40);
+        assert_eq!(done.memory[0].get(STATUS), Stati::INV as u64);
+    }
 }